@@ -0,0 +1,69 @@
+//! Plans the example domains, to measure the planner without the rest of the app.
+//!
+//! Run with `cargo bench -p bevy_htn`.
+use bevy::prelude::*;
+use bevy_htn::prelude::*;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+// the state types are shared with the examples
+#[allow(dead_code)]
+#[path = "../examples/miner/state.rs"]
+mod miner;
+#[allow(dead_code)]
+#[path = "../examples/troll/state.rs"]
+mod troll;
+
+fn plan_miner(c: &mut Criterion) {
+    let atr = AppTypeRegistry::default();
+    atr.write().register::<miner::GameState>();
+    let htn = parse_htn::<miner::GameState>(include_str!("../assets/miner.htn"))
+        .expect("Failed to parse htn");
+    let mut planner = HtnPlanner::new(&htn, &atr);
+    let state = miner::GameState {
+        energy: 100,
+        location: miner::Location::Outside,
+        ..default()
+    };
+    c.bench_function("plan miner", |b| b.iter(|| planner.plan(black_box(&state))));
+}
+
+fn plan_troll(c: &mut Criterion) {
+    let atr = AppTypeRegistry::default();
+    atr.write().register::<troll::GameState>();
+    let htn = parse_htn::<troll::GameState>(include_str!("../assets/troll.htn"))
+        .expect("Failed to parse htn");
+    let mut planner = HtnPlanner::new(&htn, &atr);
+    let base = troll::GameState {
+        trunk_health: 3,
+        next_bridge_to_check: 1,
+        can_navigate_to_enemy: true,
+        ..default()
+    };
+    // one state for each of the root task's methods, and the trunk depleted method under attack
+    let states = [
+        troll::GameState {
+            can_see_enemy: true,
+            ..base.clone()
+        },
+        troll::GameState {
+            can_see_enemy: true,
+            trunk_health: 0,
+            ..base.clone()
+        },
+        troll::GameState {
+            has_seen_enemy_recently: true,
+            ..base.clone()
+        },
+        base,
+    ];
+    c.bench_function("plan troll", |b| {
+        b.iter(|| {
+            for state in &states {
+                black_box(planner.plan(black_box(state)));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, plan_miner, plan_troll);
+criterion_main!(benches);
@@ -121,11 +121,11 @@ fn on_state_change_update_label(
 
 fn on_task_event(t: Trigger<HtnTaskEvent>, mut q: Query<&mut OverheadLabel>) {
     info!("Task event: {t:?}");
-    let HtnTaskEvent::Executing(task_name) = t.event() else {
+    let HtnTaskEvent::Executing(task) = t.event() else {
         return;
     };
     if let Ok(mut label) = q.get_mut(t.entity()) {
-        label.current_task = task_name.clone();
+        label.current_task = task.name.clone();
     }
 }
 
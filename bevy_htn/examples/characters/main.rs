@@ -409,7 +409,7 @@ fn enemy_sensors(
     let player_trans = q_player.single();
 
     for (npc_trans, children) in q_npc.iter() {
-        let Some((_sup_ent, mut state)) = q_sups.iter_mut().find(|(e, _)| children.contains(&e))
+        let Some((_sup_ent, mut state)) = q_sups.iter_mut().find(|(e, _)| children.contains(e))
         else {
             continue;
         };
@@ -1,17 +1,8 @@
 use bevy::prelude::*;
 use bevy_htn::prelude::*;
 
-#[derive(Reflect, Clone, Debug, Default)]
-#[reflect(Default)]
-pub enum Location {
-    #[default]
-    House,
-    Outside,
-    Mushroom,
-    Ore,
-    Smelter,
-    Merchant,
-}
+mod state;
+pub use state::*;
 
 // /// This is our marker components, so we can keep track of the various in-game entities
 // #[derive(Component)]
@@ -76,17 +67,6 @@ pub struct GoToSmelterOperator;
 #[reflect(Default, HtnOperator)]
 pub struct GoToMerchantOperator;
 
-#[derive(Reflect, Component, Clone, Debug, Default)]
-#[reflect(Default, Component)]
-pub struct GameState {
-    pub hunger: i32,
-    pub energy: i32,
-    pub gold: i32,
-    pub location: Location,
-    pub has_ore: bool,
-    pub has_metal: bool,
-}
-
 #[derive(Resource, Debug)]
 pub struct Rolodex {
     pub htn: Handle<HtnAsset<GameState>>,
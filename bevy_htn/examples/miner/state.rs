@@ -0,0 +1,25 @@
+//! The miner's state, shared with the planning benchmark.
+use bevy::prelude::*;
+
+#[derive(Reflect, Clone, Debug, Default)]
+#[reflect(Default)]
+pub enum Location {
+    #[default]
+    House,
+    Outside,
+    Mushroom,
+    Ore,
+    Smelter,
+    Merchant,
+}
+
+#[derive(Reflect, Component, Clone, Debug, Default)]
+#[reflect(Default, Component)]
+pub struct GameState {
+    pub hunger: i32,
+    pub energy: i32,
+    pub gold: i32,
+    pub location: Location,
+    pub has_ore: bool,
+    pub has_metal: bool,
+}
@@ -46,10 +46,6 @@ pub struct UprootTrunkOperator;
 #[reflect(Default, HtnOperator)]
 pub struct FindTrunkOperator;
 
-#[derive(Debug, Reflect, Default, Clone, HtnOperator)]
-#[reflect(Default, HtnOperator)]
-pub struct NavigateToTrunkOperator(Vec2);
-
 /// This creates a behave!{ Behave::spawn_named("Navigate To", self) }
 /// from this operator, eg inserts as a component for the behaviour tree.
 #[derive(Debug, Reflect, Default, Clone, HtnOperator, Component)]
@@ -0,0 +1,36 @@
+//! The troll's state, shared with the planning benchmark.
+use bevy::prelude::*;
+use bevy_inspector_egui::{inspector_options::std_options::NumberDisplay, prelude::*};
+
+#[derive(Reflect, Clone, Debug, Default)]
+#[reflect(Default)]
+pub enum Location {
+    #[default]
+    Unknown,
+    Player,
+    Trunk,
+    Bridge1,
+    Bridge2,
+    Bridge3,
+}
+
+#[derive(Reflect, Component, Clone, Debug, Default, InspectorOptions)]
+#[reflect(Default, Component, InspectorOptions)]
+pub struct GameState {
+    pub location_enum: Location,
+    pub location: Vec2,
+    #[inspector(min = 0, max = 3, display = NumberDisplay::Slider)]
+    pub trunk_health: i32,
+    // true if found_trunk_location is set
+    pub found_trunk: bool,
+    pub found_trunk_location: Vec2,
+    pub can_navigate_to_enemy: bool,
+    pub attacked_recently: bool,
+    pub can_see_enemy: bool,
+    pub has_seen_enemy_recently: bool,
+    pub last_enemy_location: Vec2,
+    pub next_bridge_to_check: usize,
+    pub within_melee_range: bool,
+    pub within_trunk_pickup_range: bool,
+    pub dummy_field: bool,
+}
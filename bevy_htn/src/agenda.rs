@@ -0,0 +1,124 @@
+use crate::prelude::*;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+/// High-level tasks queued for an `HtnSupervisor`, eg. orders given to a companion.
+///
+/// While the agenda isn't empty, the supervisor plans for the first task on it instead of the
+/// root task. Once that plan completes or fails, the task is removed from the agenda, an
+/// [`HtnAgendaEvent`] is triggered on the supervisor entity, and planning moves on to the next
+/// task. The root task is planned for again once the agenda is empty.
+///
+/// Modify the agenda with [`HtnAgendaCommandsExt`] so the supervisor replans straight away.
+#[derive(Component, Reflect, Debug, Default, Clone)]
+#[reflect(Component, Default)]
+pub struct HtnAgenda {
+    tasks: VecDeque<String>,
+}
+
+impl HtnAgenda {
+    pub fn new(tasks: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            tasks: tasks.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Adds a task to the end of the agenda.
+    pub fn push(&mut self, task: impl Into<String>) {
+        self.tasks.push_back(task.into());
+    }
+
+    /// Replaces every task on the agenda.
+    pub fn replace(&mut self, tasks: impl IntoIterator<Item = impl Into<String>>) {
+        self.tasks = tasks.into_iter().map(Into::into).collect();
+    }
+
+    pub fn clear(&mut self) {
+        self.tasks.clear();
+    }
+
+    /// The task currently being planned for or executed.
+    pub fn current(&self) -> Option<&str> {
+        self.tasks.front().map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.tasks.iter().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Removes the current task, if it's `task`. It might not be if the agenda was modified
+    /// while the task was being executed.
+    pub(crate) fn finish_current(&mut self, task: &str) -> bool {
+        if self.current() != Some(task) {
+            return false;
+        }
+        self.tasks.pop_front();
+        true
+    }
+}
+
+/// Event triggered on the supervisor entity when a task on its [`HtnAgenda`] is finished.
+#[derive(Event, Debug, Clone, PartialEq, Reflect)]
+pub enum HtnAgendaEvent {
+    /// Every task in the plan for this agenda task succeeded.
+    Completed(String),
+    /// No plan could be found for this agenda task, or a task in its plan failed.
+    Failed(String),
+}
+
+/// Modifies the [`HtnAgenda`] of a supervisor, inserting one if needed, and requests a replan.
+pub trait HtnAgendaCommandsExt {
+    /// Adds a task to the end of the agenda.
+    fn push_htn_task(&mut self, task: impl Into<String>) -> &mut Self;
+    /// Replaces every task on the agenda.
+    fn replace_htn_agenda(
+        &mut self,
+        tasks: impl IntoIterator<Item = impl Into<String>>,
+    ) -> &mut Self;
+    /// Removes every task from the agenda, returning to planning for the root task.
+    fn clear_htn_agenda(&mut self) -> &mut Self;
+}
+
+impl HtnAgendaCommandsExt for EntityCommands<'_> {
+    fn push_htn_task(&mut self, task: impl Into<String>) -> &mut Self {
+        let task = task.into();
+        modify_agenda(self, move |agenda| agenda.push(task))
+    }
+
+    fn replace_htn_agenda(
+        &mut self,
+        tasks: impl IntoIterator<Item = impl Into<String>>,
+    ) -> &mut Self {
+        let agenda = HtnAgenda::new(tasks);
+        modify_agenda(self, move |existing| *existing = agenda)
+    }
+
+    fn clear_htn_agenda(&mut self) -> &mut Self {
+        modify_agenda(self, HtnAgenda::clear)
+    }
+}
+
+fn modify_agenda<'a, 'b>(
+    entity_commands: &'b mut EntityCommands<'a>,
+    modify: impl FnOnce(&mut HtnAgenda) + Send + 'static,
+) -> &'b mut EntityCommands<'a> {
+    entity_commands.queue(move |mut entity: EntityWorldMut| {
+        if let Some(mut agenda) = entity.get_mut::<HtnAgenda>() {
+            modify(&mut agenda);
+        } else {
+            let mut agenda = HtnAgenda::default();
+            modify(&mut agenda);
+            entity.insert(agenda);
+        }
+        let sup_entity = entity.id();
+        entity.world_scope(|world| world.trigger_targets(ReplanRequest, sup_entity));
+    })
+}
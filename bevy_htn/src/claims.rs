@@ -0,0 +1,276 @@
+use crate::{htn::state_field, prelude::*, HtnStateTrait};
+use bevy::{
+    prelude::*,
+    reflect::{utility::reflect_hasher, ReflectRef},
+    utils::{HashMap, HashSet},
+};
+use std::{
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+/// Identifies something that can be claimed, by the value of the state field passed to `claim`,
+/// eg. the location of a coin. Keys match if their values are equal field by field, so values of
+/// different types never match.
+#[derive(Clone, Reflect)]
+#[reflect(opaque, Debug, Hash, PartialEq)]
+pub struct ClaimKey {
+    hash: u64,
+    value: Arc<dyn PartialReflect>,
+}
+
+impl ClaimKey {
+    /// The key for a value, or None if the value can't be hashed, see [`ClaimKey::is_claimable`].
+    pub fn new(value: &dyn PartialReflect) -> Option<Self> {
+        let mut hasher = reflect_hasher();
+        hash_value(value, &mut hasher)?;
+        Some(Self {
+            hash: hasher.finish(),
+            value: value.clone_value().into(),
+        })
+    }
+
+    /// The key for the value of a state field, or None if the field doesn't exist or can't be
+    /// hashed.
+    pub fn from_field<T: HtnStateTrait>(state: &T, field: &str) -> Option<Self> {
+        let reflected = state.reflect_ref().as_struct().ok()?;
+        state_field(reflected, field).and_then(Self::new)
+    }
+
+    /// True if the value can be claimed. Values are hashed with `reflect_hash` where their type
+    /// supports it, otherwise field by field, with floats hashed by value, so types like `Vec2`
+    /// can be claimed. Maps, sets, and opaque types without `#[reflect(Hash)]` can't be.
+    pub fn is_claimable(value: &dyn PartialReflect) -> bool {
+        hash_value(value, &mut reflect_hasher()).is_some()
+    }
+}
+
+/// Hashes a value so that values equal by `reflect_partial_eq` have equal hashes.
+fn hash_value(value: &dyn PartialReflect, hasher: &mut impl Hasher) -> Option<()> {
+    if let Some(hash) = value.reflect_hash() {
+        hasher.write_u64(hash);
+        return Some(());
+    }
+    value.reflect_type_path().hash(hasher);
+    match value.reflect_ref() {
+        ReflectRef::Struct(s) => s.iter_fields().try_for_each(|f| hash_value(f, hasher)),
+        ReflectRef::TupleStruct(s) => s.iter_fields().try_for_each(|f| hash_value(f, hasher)),
+        ReflectRef::Tuple(t) => t.iter_fields().try_for_each(|f| hash_value(f, hasher)),
+        ReflectRef::List(l) => {
+            hasher.write_usize(l.len());
+            l.iter().try_for_each(|item| hash_value(item, hasher))
+        }
+        ReflectRef::Array(a) => a.iter().try_for_each(|item| hash_value(item, hasher)),
+        ReflectRef::Enum(e) => {
+            e.variant_name().hash(hasher);
+            e.iter_fields()
+                .try_for_each(|f| hash_value(f.value(), hasher))
+        }
+        ReflectRef::Opaque(v) => {
+            let float = v
+                .try_downcast_ref::<f32>()
+                .map(|f| *f as f64)
+                .or_else(|| v.try_downcast_ref::<f64>().copied())?;
+            // -0.0 == 0.0, so they have to hash the same
+            let float = if float == 0.0 { 0.0 } else { float };
+            hasher.write_u64(float.to_bits());
+            Some(())
+        }
+        _ => None,
+    }
+}
+
+/// Compares values the way [`hash_value`] hashes them. `reflect_partial_eq` alone isn't enough,
+/// because it returns None when comparing some dynamic values, like the clones keys hold.
+fn values_eq(a: &dyn PartialReflect, b: &dyn PartialReflect) -> bool {
+    if let Some(eq) = a.reflect_partial_eq(b) {
+        return eq;
+    }
+    if a.reflect_type_path() != b.reflect_type_path() {
+        return false;
+    }
+    fn all_eq<'a>(
+        a: impl ExactSizeIterator<Item = &'a dyn PartialReflect>,
+        b: impl ExactSizeIterator<Item = &'a dyn PartialReflect>,
+    ) -> bool {
+        a.len() == b.len() && a.zip(b).all(|(a, b)| values_eq(a, b))
+    }
+    match (a.reflect_ref(), b.reflect_ref()) {
+        (ReflectRef::Struct(a), ReflectRef::Struct(b)) => all_eq(a.iter_fields(), b.iter_fields()),
+        (ReflectRef::TupleStruct(a), ReflectRef::TupleStruct(b)) => {
+            all_eq(a.iter_fields(), b.iter_fields())
+        }
+        (ReflectRef::Tuple(a), ReflectRef::Tuple(b)) => all_eq(a.iter_fields(), b.iter_fields()),
+        (ReflectRef::List(a), ReflectRef::List(b)) => all_eq(a.iter(), b.iter()),
+        (ReflectRef::Array(a), ReflectRef::Array(b)) => all_eq(a.iter(), b.iter()),
+        (ReflectRef::Enum(a), ReflectRef::Enum(b)) => {
+            a.variant_name() == b.variant_name()
+                && all_eq(
+                    a.iter_fields().map(|f| f.value()),
+                    b.iter_fields().map(|f| f.value()),
+                )
+        }
+        _ => false,
+    }
+}
+
+impl Hash for ClaimKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
+}
+
+impl PartialEq for ClaimKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && values_eq(self.value.as_ref(), other.value.as_ref())
+    }
+}
+
+impl Eq for ClaimKey {}
+
+impl std::fmt::Debug for ClaimKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ClaimKey").field(&self.value).finish()
+    }
+}
+
+impl std::fmt::Display for ClaimKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {:?}", self.value.reflect_type_path(), self.value)
+    }
+}
+
+/// Reservations shared by every supervisor, so agents don't target the same thing.
+///
+/// A plan with `claim(field)` effects claims the values of those fields for its supervisor when
+/// it starts running, and other supervisors' `unclaimed(field)` conditions fail for them while
+/// it runs. Claims are released when the plan completes, fails or is aborted, or the supervisor
+/// is despawned.
+#[derive(Resource, Debug, Default)]
+pub struct HtnClaims {
+    owners: HashMap<ClaimKey, Entity>,
+}
+
+impl HtnClaims {
+    /// The supervisor holding the claim, if any.
+    pub fn owner(&self, key: &ClaimKey) -> Option<Entity> {
+        self.owners.get(key).copied()
+    }
+
+    /// True if a supervisor other than `sup_entity` holds the claim.
+    pub fn is_claimed_by_other(&self, key: &ClaimKey, sup_entity: Entity) -> bool {
+        self.owner(key).is_some_and(|owner| owner != sup_entity)
+    }
+
+    /// Claims `key` for the supervisor, returning false if another supervisor holds it.
+    pub fn claim(&mut self, key: ClaimKey, sup_entity: Entity) -> bool {
+        match self.owners.entry(key) {
+            bevy::utils::Entry::Occupied(entry) => *entry.get() == sup_entity,
+            bevy::utils::Entry::Vacant(entry) => {
+                entry.insert(sup_entity);
+                true
+            }
+        }
+    }
+
+    /// Releases every claim held by the supervisor.
+    pub fn release_all(&mut self, sup_entity: Entity) {
+        self.owners.retain(|_, owner| *owner != sup_entity);
+    }
+
+    /// The claims held by the supervisor.
+    pub fn claims_of(&self, sup_entity: Entity) -> impl Iterator<Item = &ClaimKey> {
+        self.owners
+            .iter()
+            .filter(move |(_, owner)| **owner == sup_entity)
+            .map(|(key, _)| key)
+    }
+
+    /// Claims held by supervisors other than `sup_entity`, for planning with
+    /// [`HtnPlanner::with_claimed`].
+    pub fn claimed_by_others(&self, sup_entity: Entity) -> HashSet<ClaimKey> {
+        self.owners
+            .iter()
+            .filter(|(_, owner)| **owner != sup_entity)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.owners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.owners.is_empty()
+    }
+}
+
+/// Returns the first `unclaimed` condition whose field value is in `claimed`.
+pub(crate) fn first_claimed_condition<'c, T: HtnStateTrait>(
+    conditions: &'c [HtnCondition],
+    state: &T,
+    claimed: &HashSet<ClaimKey>,
+) -> Option<&'c HtnCondition> {
+    if claimed.is_empty() {
+        return None;
+    }
+    conditions.iter().find(|cond| match cond {
+        HtnCondition::Unclaimed { field, .. } => {
+            ClaimKey::from_field(state, field).is_some_and(|key| claimed.contains(&key))
+        }
+        _ => false,
+    })
+}
+
+/// Claims the plan's claims for its supervisor when it's inserted, releasing any it held before.
+/// If another supervisor got there first, the plan is aborted, and we replan if the supervisor's
+/// [`ReplanPolicy`] replans invalidated plans.
+pub(crate) fn on_plan_claims(
+    t: Trigger<OnInsert, Plan>,
+    mut q: Query<(&mut Plan, Option<&ReplanPolicy>)>,
+    mut claims: ResMut<HtnClaims>,
+    mut commands: Commands,
+) {
+    let sup_entity = t.entity();
+    let Ok((mut plan, policy)) = q.get_mut(sup_entity) else {
+        return;
+    };
+    claims.release_all(sup_entity);
+    if plan.claims().is_empty() {
+        return;
+    }
+    if let Some(taken) = plan
+        .claims()
+        .iter()
+        .find(|key| claims.is_claimed_by_other(key, sup_entity))
+    {
+        debug!("Claim {taken} already taken, aborting plan for {sup_entity:?}");
+        plan.abort();
+        if policy
+            .copied()
+            .unwrap_or_default()
+            .replans_on_invalidation()
+        {
+            commands.trigger_targets(ReplanRequest, sup_entity);
+        }
+        return;
+    }
+    for key in plan.claims().to_vec() {
+        claims.claim(key, sup_entity);
+    }
+}
+
+/// Releases claims when a supervisor's plan is removed, eg. when it's despawned.
+pub(crate) fn on_plan_removed(t: Trigger<OnRemove, Plan>, mut claims: ResMut<HtnClaims>) {
+    claims.release_all(t.entity());
+}
+
+/// Releases the claims of plans that have finished, or been aborted.
+pub(crate) fn release_finished_claims(q: Query<&Plan>, mut claims: ResMut<HtnClaims>) {
+    if claims.is_empty() {
+        return;
+    }
+    claims
+        .owners
+        .retain(|_, owner| q.get(*owner).is_ok_and(|plan| plan.status().is_active()));
+}
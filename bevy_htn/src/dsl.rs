@@ -53,6 +53,14 @@ fn parse_enum(val_str: &str, context: &str) -> Result<(String, String), HtnErr>
 fn parse_condition(pair: Pair<Rule>) -> Result<HtnCondition, HtnErr> {
     let syntax = pair.as_str().to_string();
     let mut pairs = pair.into_inner();
+    if pairs.peek().map(|p| p.as_rule()) == Some(Rule::unclaimed_condition) {
+        // eg: unclaimed(foo)
+        let field = pairs.next().unwrap().into_inner().next().unwrap();
+        return Ok(HtnCondition::Unclaimed {
+            field: field.as_str().to_string(),
+            syntax,
+        });
+    }
     // eg:  foo >= 10
     let field = pairs.next().unwrap().as_str().to_string(); // "foo"
     let op = pairs.next().unwrap().as_rule(); // Rule::op_gte
@@ -169,6 +177,10 @@ fn parse_effect(pair: Pair<Rule>) -> Result<Effect, HtnErr> {
     // EG: foo = 10
     // the LHS state field name, ie "foo"
     let field = parts.next().unwrap().as_str().to_string();
+    if effect_rule == Rule::claim_effect {
+        // eg: claim(foo)
+        return Ok(Effect::Claim { field, syntax });
+    }
     // the RHS:
     let val_pair = parts.next().unwrap();
     let val_rule = val_pair.as_rule(); // Rule::int_value
@@ -288,6 +300,45 @@ fn parse_primitive_task<T: HtnStateTrait>(pair: Pair<Rule>) -> Result<PrimitiveT
                     builder = builder.precondition(condition);
                 }
             }
+            Rule::duration_statement => {
+                let syntax = stmt.as_str().trim().to_string();
+                let val_pair = stmt.into_inner().next().unwrap();
+                let val_str = val_pair.as_str();
+                let duration = match val_pair.as_rule() {
+                    Rule::float_value => TaskDuration::Fixed(parse_f32(val_str, &syntax)?),
+                    Rule::int_value => TaskDuration::Fixed(parse_i32(val_str, &syntax)? as f32),
+                    _ => TaskDuration::Field(val_str.to_string()),
+                };
+                builder = builder.duration(duration);
+            }
+            Rule::timeout_statement => {
+                let syntax = stmt.as_str().trim().to_string();
+                let val_pair = stmt.into_inner().next().unwrap();
+                let timeout = match val_pair.as_rule() {
+                    Rule::float_value => parse_f32(val_pair.as_str(), &syntax)?,
+                    _ => parse_i32(val_pair.as_str(), &syntax)? as f32,
+                };
+                builder = builder.timeout(timeout);
+            }
+            Rule::retries_statement => {
+                let syntax = stmt.as_str().trim().to_string();
+                let val_str = stmt.into_inner().next().unwrap().as_str();
+                let retries =
+                    usize::try_from(parse_i32(val_str, &syntax)?).map_err(|_| HtnErr::Int {
+                        syntax: val_str.to_string(),
+                        details: format!("Retries can't be negative in: `{syntax}`"),
+                    })?;
+                builder = builder.retries(retries);
+            }
+            Rule::retry_delay_statement => {
+                let syntax = stmt.as_str().trim().to_string();
+                let val_pair = stmt.into_inner().next().unwrap();
+                let delay = match val_pair.as_rule() {
+                    Rule::float_value => parse_f32(val_pair.as_str(), &syntax)?,
+                    _ => parse_i32(val_pair.as_str(), &syntax)? as f32,
+                };
+                builder = builder.retry_delay(delay);
+            }
             _ => {}
         }
     }
@@ -32,9 +32,23 @@ pub enum HtnErr {
     Schema {
         details: String,
     },
+    Subtask {
+        task: String,
+        subtask: String,
+    },
+    Duration {
+        task: String,
+        details: String,
+    },
     ParserError {
         details: String,
     },
+    UnknownTask {
+        task: String,
+    },
+    Planning {
+        details: String,
+    },
 }
 
 impl std::fmt::Display for HtnErr {
@@ -68,9 +82,21 @@ impl std::fmt::Display for HtnErr {
             HtnErr::Schema { details } => {
                 write!(f, "Schema error: {details}")
             }
+            HtnErr::Subtask { task, subtask } => {
+                write!(f, "Unknown subtask `{subtask}` in compound task `{task}`")
+            }
+            HtnErr::Duration { task, details } => {
+                write!(f, "Invalid duration in task `{task}`: {details}")
+            }
             HtnErr::ParserError { details } => {
                 write!(f, "HTN parsing error: {details}")
             }
+            HtnErr::UnknownTask { task } => {
+                write!(f, "Unknown task `{task}`")
+            }
+            HtnErr::Planning { details } => {
+                write!(f, "Planning error: {details}")
+            }
         }
     }
 }
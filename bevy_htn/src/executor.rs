@@ -1,5 +1,9 @@
-use crate::{prelude::*, HtnStateTrait};
-use bevy::prelude::*;
+use crate::{prelude::*, world_state::is_world_effect, HtnStateTrait};
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    tasks::{self, block_on, futures_lite::future, AsyncComputeTaskPool},
+};
 use bevy_behave::prelude::*;
 use std::marker::PhantomData;
 
@@ -20,19 +24,41 @@ impl<T: HtnStateTrait> Plugin for HtnExecutorPlugin<T> {
         app.add_systems(
             Update,
             (
-                task_finished,
+                task_finished::<T>,
                 when_to_replan_system::<T>,
                 check_plans_still_valid::<T>,
+                poll_async_plans::<T>,
+                advance_time_sliced_plans::<T>,
+                replan_on_schedule::<T>,
             ),
         );
+        app.add_systems(PostUpdate, process_replan_queue::<T>);
+        // the events these observe are shared by every state type, they ignore supervisors with
+        // other state types, which are handled by their own plugin.
         app.add_observer(on_exec_next_task::<T>);
-        // TODO each T instance of pluin will add this as a global obs, not what we want:
-        app.add_observer(on_plan_added);
+        app.add_observer(on_retry_task::<T>);
+        app.add_observer(on_plan_added::<T>);
         app.add_observer(on_task_complete::<T>);
         app.add_observer(on_replan_request::<T>);
     }
 }
 
+/// Systems that aren't specific to a state type, added once by the shared plugin.
+pub(crate) fn add_shared_systems(app: &mut App) {
+    app.add_systems(
+        Update,
+        (
+            warn_overrunning_tasks,
+            time_out_tasks,
+            start_pending_retries,
+        ),
+    );
+    app.add_systems(First, reset_replan_budget);
+}
+
+/// Supervisors with state `T`, for observers of events shared by every state type.
+type OwnSupervisors<'w, 's, T> = Query<'w, 's, (), With<HtnSupervisor<T>>>;
+
 pub trait HtnSupervisorExt {
     fn spawn_htn_supervisor<T: HtnStateTrait>(
         &mut self,
@@ -77,10 +103,156 @@ pub struct ReplanRequest;
 /// This entity is the parent of the HTN operator entities.
 /// It holds the HTN asset and the current plan, and is a direct child of the troll.
 #[derive(Component, Reflect)]
+#[require(PlanIdCounter, PlanHistory)]
 pub struct HtnSupervisor<T: HtnStateTrait> {
     pub htn_handle: Handle<HtnAsset<T>>,
 }
 
+/// Gives each plan inserted on a supervisor an id, counting up from 1, so ids are the same
+/// every run.
+#[derive(Component, Reflect, Debug, Default, Clone)]
+#[reflect(Component, Default)]
+pub struct PlanIdCounter {
+    last: u32,
+}
+
+impl PlanIdCounter {
+    /// The id of the most recent plan, or 0 if there hasn't been one.
+    pub fn last(&self) -> u32 {
+        self.last
+    }
+
+    fn next(&mut self) -> u32 {
+        self.last += 1;
+        self.last
+    }
+}
+
+/// Plans for arbitrary tasks from a supervisor's current state, using its HTN, eg. to ask whether
+/// an agent could accomplish a task from where it is now, and how.
+///
+/// The plans aren't executed, insert the result on the supervisor to do that.
+#[derive(SystemParam)]
+pub struct HtnSupervisorPlanner<'w, 's, T: HtnStateTrait> {
+    supervisors: Query<'w, 's, (&'static HtnSupervisor<T>, &'static T)>,
+    assets: Res<'w, Assets<HtnAsset<T>>>,
+    atr: Res<'w, AppTypeRegistry>,
+}
+
+impl<T: HtnStateTrait> HtnSupervisorPlanner<'_, '_, T> {
+    /// Plans for the named tasks in order, see [`HtnPlanner::plan_tasks`].
+    pub fn plan_tasks(&self, sup_entity: Entity, tasks: &[&str]) -> Result<Plan, HtnErr> {
+        let Ok((htn_supervisor, state)) = self.supervisors.get(sup_entity) else {
+            return Err(HtnErr::Planning {
+                details: format!("HtnSupervisor {sup_entity:?} not found"),
+            });
+        };
+        let Some(htn) = self.assets.get(&htn_supervisor.htn_handle).map(|h| &h.htn) else {
+            return Err(HtnErr::Planning {
+                details: "HtnAsset not found".to_string(),
+            });
+        };
+        HtnPlanner::new(htn, self.atr.as_ref()).plan_tasks(state, tasks)
+    }
+
+    /// Plans for a single named task, see [`HtnPlanner::plan_tasks`].
+    pub fn plan_task(&self, sup_entity: Entity, task: &str) -> Result<Plan, HtnErr> {
+        self.plan_tasks(sup_entity, &[task])
+    }
+}
+
+/// Insert on an `HtnSupervisor` entity to compute its plans on the `AsyncComputeTaskPool`,
+/// rather than on the main thread when the `ReplanRequest` is handled.
+///
+/// The supervisor's state and domain are snapshotted when planning starts, and
+/// [`PlanningInProgress`] is present until the plan is ready.
+#[derive(Component, Reflect, Debug, Default, Clone)]
+#[reflect(Component, Default)]
+pub struct AsyncPlanning;
+
+/// Insert on an `HtnSupervisor` entity to compute its plans over several frames on the main
+/// thread, running at most `steps_per_frame` planner steps each frame.
+///
+/// Unlike [`AsyncPlanning`], the work done per frame doesn't depend on thread scheduling, so
+/// planning finishes on the same frame everywhere, eg. for lockstep networking.
+/// The supervisor's state is snapshotted when planning starts, and [`PlanningInProgress`] is
+/// present until the plan is ready.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component, Default)]
+pub struct TimeSlicedPlanning {
+    pub steps_per_frame: usize,
+}
+
+impl Default for TimeSlicedPlanning {
+    fn default() -> Self {
+        Self {
+            steps_per_frame: 10,
+        }
+    }
+}
+
+/// Insert on an `HtnSupervisor` entity to change how many steps its planner runs before giving
+/// up, see [`HtnPlanner::with_max_steps`]. Without it, planning fails after
+/// [`DEFAULT_MAX_STEPS`].
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component, Default)]
+pub struct MaxPlanningSteps(pub usize);
+
+impl Default for MaxPlanningSteps {
+    fn default() -> Self {
+        Self(DEFAULT_MAX_STEPS)
+    }
+}
+
+/// Insert on an `HtnSupervisor` entity to repair its plan when a task fails, rather than
+/// replanning from the root task.
+///
+/// The compound task the failed task was decomposed from is planned again from the current
+/// state, trying the methods after the one that failed, see [`HtnPlanner::repair`]. If that
+/// doesn't lead to a plan, we replan as usual.
+#[derive(Component, Reflect, Debug, Default, Clone)]
+#[reflect(Component, Default)]
+pub struct PlanRepair;
+
+/// Insert on an `HtnSupervisor` entity to fail tasks still executing after this many seconds,
+/// for tasks without their own `timeout`. Timeouts that aren't positive are ignored, with a
+/// warning.
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(Component)]
+pub struct DefaultTaskTimeout(pub f32);
+
+impl DefaultTaskTimeout {
+    /// The timeout in seconds, or None if it isn't positive.
+    pub fn secs(&self) -> Option<f32> {
+        (self.0 > 0.0).then_some(self.0)
+    }
+}
+
+/// Present on an `HtnSupervisor` entity while its next plan is being computed, when using
+/// [`AsyncPlanning`] or [`TimeSlicedPlanning`].
+#[derive(Component, Debug, Default)]
+pub struct PlanningInProgress {
+    replan_requested: bool,
+}
+
+impl PlanningInProgress {
+    /// True if another replan was requested while planning, in which case we'll plan again once
+    /// this one finishes.
+    pub fn replan_requested(&self) -> bool {
+        self.replan_requested
+    }
+}
+
+// the plan, and whether planning failed
+#[derive(Component)]
+struct AsyncPlanTask(tasks::Task<(Plan, bool)>);
+
+#[derive(Component)]
+struct TimeSlicedProgress<T: HtnStateTrait> {
+    progress: PlannerProgress<T>,
+    agenda_item: Option<String>,
+}
+
 // #[derive(Event)]
 // pub struct KillRunningTaskChildren;
 
@@ -106,15 +278,39 @@ pub struct HtnSupervisor<T: HtnStateTrait> {
 
 // do we need to replan? is the current plan still valid?
 fn when_to_replan_system<T: HtnStateTrait>(
-    mut q: Query<(Entity, &HtnSupervisor<T>, &T, &mut Plan), Or<(Added<T>, Changed<T>)>>,
+    mut q: Query<
+        (
+            Entity,
+            &HtnSupervisor<T>,
+            Ref<T>,
+            Option<&mut Plan>,
+            Option<&ReplanPolicy>,
+            Option<&mut ObservedState<T>>,
+        ),
+        Or<(Added<T>, Changed<T>)>,
+    >,
     mut commands: Commands,
     assets: Res<Assets<HtnAsset<T>>>,
     atr: Res<AppTypeRegistry>,
 ) {
-    for (sup_entity, htn_supervisor, state, mut plan) in q.iter_mut() {
+    for (sup_entity, htn_supervisor, state, plan, policy, observed) in q.iter_mut() {
+        let Some(mut plan) = plan else {
+            continue;
+        };
+        // the observed fields are only kept up to date while the plan is active
+        let observed = observed.filter(|_| plan.status().is_active());
+        if observed.is_none() {
+            commands.entity(sup_entity).remove::<ObservedState<T>>();
+        }
+        let policy = policy.copied().unwrap_or_default();
+        // without replanning on invalidation, an invalid plan fails when its next task's
+        // preconditions aren't met, so there's no need to check it on every change
+        if !policy.replans_on_invalidation() {
+            continue;
+        }
         let Some(htn) = assets.get(&htn_supervisor.htn_handle).map(|h| &h.htn) else {
             warn!("HtnAsset not found");
-            return;
+            continue;
         };
         // the game state has changed, is the current plan still valid?
         if !plan.check_validity(htn, state.clone(), atr.as_ref()) {
@@ -123,93 +319,561 @@ fn when_to_replan_system<T: HtnStateTrait>(
             continue;
         }
 
-        // replan on any change:
+        if !policy.on_change {
+            continue;
+        }
+        // a replan can only find a different plan if a field it depends on changed
+        if let (false, Some(mut observed)) = (state.is_added(), observed) {
+            if observed.plan_id == plan.id() && observed.update(state.as_ref(), atr.as_ref()) {
+                debug!("Not replanning {sup_entity:?}, no relevant fields changed");
+                continue;
+            }
+        }
         commands.trigger_targets(ReplanRequest, sup_entity);
     }
 }
 
+/// The values of the fields the supervisor's plan depends on, see [`Plan::relevant_fields`], as
+/// they were when it last checked whether to replan, to tell whether any have changed since.
+///
+/// Taken when a plan is inserted, and removed once the plan isn't active.
+#[derive(Component)]
+struct ObservedState<T: HtnStateTrait> {
+    plan_id: u32,
+    fields: Vec<(FieldPath, Box<dyn PartialReflect>)>,
+    phantom: PhantomData<T>,
+}
+
+impl<T: HtnStateTrait> ObservedState<T> {
+    fn new(plan: &Plan, htn: &HTN<T>, state: &T, atr: &AppTypeRegistry) -> Option<Self> {
+        let relevant = plan.relevant_fields(htn)?;
+        let state = state.reflect_ref().as_struct().ok()?;
+        let fields = relevant
+            .iter()
+            .filter_map(|name| {
+                let path = FieldPath::resolve(state, name)?;
+                let value = owned_value(path.get(state)?, atr);
+                Some((path, value))
+            })
+            .collect();
+        Some(Self {
+            plan_id: plan.id(),
+            fields,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Records the current values of the fields, returning true if none of them changed.
+    fn update(&mut self, state: &T, atr: &AppTypeRegistry) -> bool {
+        let Ok(state) = state.reflect_ref().as_struct() else {
+            return false;
+        };
+        let mut unchanged = true;
+        for (path, value) in self.fields.iter_mut() {
+            let Some(current) = path.get(state) else {
+                continue;
+            };
+            if current.reflect_partial_eq(value.as_ref()) != Some(true) {
+                *value = owned_value(current, atr);
+                unchanged = false;
+            }
+        }
+        unchanged
+    }
+}
+
+/// Copies a field's value, as its concrete type if it's registered, as `reflect_partial_eq`
+/// can't always compare a concrete value with a dynamic one.
+fn owned_value(value: &dyn PartialReflect, atr: &AppTypeRegistry) -> Box<dyn PartialReflect> {
+    value
+        .get_represented_type_info()
+        .and_then(|info| {
+            atr.read()
+                .get_type_data::<ReflectFromReflect>(info.type_id())?
+                .from_reflect(value)
+        })
+        .map(|value| value.into_partial_reflect())
+        .unwrap_or_else(|| value.clone_value())
+}
+
+/// Plans can become invalid without the state changing, as tasks complete, so they're checked
+/// again whenever they change.
 fn check_plans_still_valid<T: HtnStateTrait>(
-    mut q: Query<(Entity, &HtnSupervisor<T>, &T, &mut Plan)>,
+    mut q: Query<
+        (
+            Entity,
+            &HtnSupervisor<T>,
+            &T,
+            &mut Plan,
+            Option<&ReplanPolicy>,
+        ),
+        Changed<Plan>,
+    >,
     assets: Res<Assets<HtnAsset<T>>>,
     atr: Res<AppTypeRegistry>,
+    mut commands: Commands,
 ) {
-    for (_sup_entity, htn_supervisor, state, mut plan) in q.iter_mut() {
+    for (sup_entity, htn_supervisor, state, mut plan, policy) in q.iter_mut() {
+        if !policy
+            .copied()
+            .unwrap_or_default()
+            .replans_on_invalidation()
+        {
+            continue;
+        }
         let Some(htn) = assets.get(&htn_supervisor.htn_handle).map(|h| &h.htn) else {
             warn!("HtnAsset not found");
             continue;
         };
-        if !plan.check_validity(htn, state.clone(), atr.as_ref()) {
+        if plan.status().is_active() && !plan.check_validity(htn, state.clone(), atr.as_ref()) {
             plan.abort();
+            commands.trigger_targets(ReplanRequest, sup_entity);
         }
     }
 }
 
+/// The supervisor components needed to start planning.
+pub(crate) type ReplanData<'a, T> = (
+    &'a HtnSupervisor<T>,
+    &'a T,
+    Option<&'a Plan>,
+    Has<PlanTrace>,
+    Has<AsyncPlanning>,
+    Has<TimeSlicedPlanning>,
+    Option<&'a MaxPlanningSteps>,
+    Option<&'a mut PlanningInProgress>,
+    Option<&'a mut HtnAgenda>,
+);
+
+#[allow(clippy::too_many_arguments)]
 fn on_replan_request<T: HtnStateTrait>(
     t: Trigger<ReplanRequest>,
     assets: Res<Assets<HtnAsset<T>>>,
-    q: Query<(&HtnSupervisor<T>, &Parent, &T, Option<&Plan>)>,
+    mut q: Query<ReplanData<T>>,
+    mut q_policy: Query<(&ReplanPolicy, &mut ReplanTiming)>,
+    scheduler: Option<ResMut<ReplanScheduler>>,
+    claims: Res<HtnClaims>,
     atr: Res<AppTypeRegistry>,
+    time: Res<Time>,
     mut commands: Commands,
 ) {
     // these are triggering on the sup entity that has the Plan, State and HTNSupervisor.
+    if !q.contains(t.entity()) {
+        // a supervisor with another state type
+        return;
+    }
     info!("Replan request event for entity: {:?}", t.entity());
+    if !allow_replan(&mut q_policy, t.entity(), time.elapsed_secs()) {
+        return;
+    }
+
+    if let Some(mut scheduler) = scheduler {
+        scheduler.enqueue(t.entity());
+        return;
+    }
+    replan(
+        t.entity(),
+        &mut q,
+        &assets,
+        &claims,
+        atr.as_ref(),
+        &mut commands,
+    );
+}
 
-    let Ok((htn_supervisor, _parent, state, opt_plan)) = q.get(t.entity()) else {
+/// Plans for the supervisor immediately, or starts planning if it uses [`AsyncPlanning`] or
+/// [`TimeSlicedPlanning`].
+pub(crate) fn replan<T: HtnStateTrait>(
+    sup_entity: Entity,
+    q: &mut Query<ReplanData<T>>,
+    assets: &Assets<HtnAsset<T>>,
+    claims: &HtnClaims,
+    atr: &AppTypeRegistry,
+    commands: &mut Commands,
+) {
+    let Ok((
+        htn_supervisor,
+        state,
+        opt_plan,
+        tracing,
+        asynchronous,
+        time_sliced,
+        max_steps,
+        in_progress,
+        mut agenda,
+    )) = q.get_mut(sup_entity)
+    else {
         warn!("HtnSupervisor not found");
         return;
     };
+    if let Some(mut in_progress) = in_progress {
+        debug!("Already planning, will replan when finished");
+        in_progress.replan_requested = true;
+        return;
+    }
     let Some(htn) = assets.get(&htn_supervisor.htn_handle).map(|h| &h.htn) else {
         warn!("HtnAsset not found");
         return;
     };
+    // plan for the current agenda item if there is one, otherwise the root task
+    let agenda_item = agenda
+        .as_ref()
+        .and_then(|a| a.current())
+        .map(str::to_string);
+    let start_task = match &agenda_item {
+        Some(item) => match htn.task_id(item) {
+            Some(id) => id,
+            None => {
+                error!("Agenda task `{item}` not found in HTN");
+                finish_agenda_item(sup_entity, item, false, agenda.as_deref_mut(), commands);
+                commands.trigger_targets(ReplanRequest, sup_entity);
+                return;
+            }
+        },
+        None => htn.root_task_id(),
+    };
+    let claimed = claims.claimed_by_others(sup_entity);
+    let max_steps = max_steps.map_or(DEFAULT_MAX_STEPS, |m| m.0);
 
-    let mut planner = HtnPlanner::new(htn, atr.as_ref());
-    let new_plan = planner.plan(state);
+    if asynchronous {
+        let htn = htn.clone();
+        let atr = atr.clone();
+        let state = state.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let mut planner = HtnPlanner::new(&htn, &atr)
+                .with_claimed(claimed)
+                .with_max_steps(max_steps);
+            if tracing {
+                planner = planner.with_trace();
+            }
+            planner.start_with_tasks(&state, &[start_task]);
+            let plan = planner
+                .plan_steps(usize::MAX)
+                .expect("Planning always finishes within the step limit");
+            (plan.for_agenda_item(agenda_item), planner.failed())
+        });
+        commands
+            .entity(sup_entity)
+            .insert((PlanningInProgress::default(), AsyncPlanTask(task)));
+        return;
+    }
+
+    if time_sliced {
+        let mut planner = HtnPlanner::new(htn, atr)
+            .with_claimed(claimed)
+            .with_max_steps(max_steps);
+        if tracing {
+            planner = planner.with_trace();
+        }
+        planner.start_with_tasks(state, &[start_task]);
+        commands.entity(sup_entity).insert((
+            PlanningInProgress::default(),
+            TimeSlicedProgress {
+                progress: planner.into_progress(),
+                agenda_item,
+            },
+        ));
+        return;
+    }
+
+    let mut planner = HtnPlanner::new(htn, atr)
+        .with_claimed(claimed)
+        .with_max_steps(max_steps);
+    if tracing {
+        planner = planner.with_trace();
+    }
+    planner.start_with_tasks(state, &[start_task]);
+    let new_plan = planner
+        .plan_steps(usize::MAX)
+        .expect("Planning always finishes within the step limit")
+        .for_agenda_item(agenda_item);
+    let Some(new_plan) = check_agenda_plan(
+        sup_entity,
+        new_plan,
+        planner.failed(),
+        agenda.as_deref_mut(),
+        commands,
+    ) else {
+        return;
+    };
+    use_new_plan(sup_entity, new_plan, opt_plan, commands);
+}
+
+/// Reports a finished plan for an agenda item if it failed or had nothing to do, returning the
+/// plan if it should be executed.
+fn check_agenda_plan(
+    sup_entity: Entity,
+    new_plan: Plan,
+    failed: bool,
+    agenda: Option<&mut HtnAgenda>,
+    commands: &mut Commands,
+) -> Option<Plan> {
+    let Some(item) = new_plan.agenda_item() else {
+        return Some(new_plan);
+    };
+    if !failed && !new_plan.tasks.is_empty() {
+        return Some(new_plan);
+    }
+    if failed {
+        warn!("No plan found for agenda task `{item}`");
+    }
+    finish_agenda_item(sup_entity, item, !failed, agenda, commands);
+    commands.trigger_targets(ReplanRequest, sup_entity);
+    None
+}
+
+/// Removes the item from the agenda if it's still the current one, and reports the result.
+fn finish_agenda_item(
+    sup_entity: Entity,
+    item: &str,
+    success: bool,
+    agenda: Option<&mut HtnAgenda>,
+    commands: &mut Commands,
+) {
+    if !agenda.is_some_and(|agenda| agenda.finish_current(item)) {
+        debug!("Agenda task `{item}` is no longer current, not reporting result");
+        return;
+    }
+    let event = if success {
+        HtnAgendaEvent::Completed(item.to_string())
+    } else {
+        HtnAgendaEvent::Failed(item.to_string())
+    };
+    commands.trigger_targets(event, sup_entity);
+}
+
+/// Checks for finished async plans.
+fn poll_async_plans<T: HtnStateTrait>(
+    mut q: Query<(
+        Entity,
+        &HtnSupervisor<T>,
+        &T,
+        Option<&Plan>,
+        &PlanningInProgress,
+        &mut AsyncPlanTask,
+        Option<&mut HtnAgenda>,
+    )>,
+    assets: Res<Assets<HtnAsset<T>>>,
+    atr: Res<AppTypeRegistry>,
+    mut commands: Commands,
+) {
+    for (sup_entity, htn_supervisor, state, opt_plan, in_progress, mut task, mut agenda) in
+        q.iter_mut()
+    {
+        let Some((new_plan, failed)) = block_on(future::poll_once(&mut task.0)) else {
+            continue;
+        };
+        commands
+            .entity(sup_entity)
+            .remove::<(PlanningInProgress, AsyncPlanTask)>();
+        let Some(htn) = assets.get(&htn_supervisor.htn_handle).map(|h| &h.htn) else {
+            warn!("HtnAsset not found");
+            continue;
+        };
+        use_background_plan(
+            sup_entity,
+            new_plan,
+            failed,
+            htn,
+            state,
+            opt_plan,
+            in_progress,
+            agenda.as_deref_mut(),
+            atr.as_ref(),
+            &mut commands,
+        );
+    }
+}
+
+/// Runs the next slice of planning steps for each supervisor using [`TimeSlicedPlanning`].
+fn advance_time_sliced_plans<T: HtnStateTrait>(
+    mut q: Query<(
+        Entity,
+        &HtnSupervisor<T>,
+        &TimeSlicedPlanning,
+        &T,
+        Option<&Plan>,
+        &PlanningInProgress,
+        &mut TimeSlicedProgress<T>,
+        Option<&mut HtnAgenda>,
+    )>,
+    assets: Res<Assets<HtnAsset<T>>>,
+    atr: Res<AppTypeRegistry>,
+    mut commands: Commands,
+) {
+    for (
+        sup_entity,
+        htn_supervisor,
+        time_sliced,
+        state,
+        opt_plan,
+        in_progress,
+        mut progress,
+        mut agenda,
+    ) in q.iter_mut()
+    {
+        let Some(htn) = assets.get(&htn_supervisor.htn_handle).map(|h| &h.htn) else {
+            warn!("HtnAsset not found");
+            continue;
+        };
+        let mut planner =
+            HtnPlanner::resume(htn, atr.as_ref(), std::mem::take(&mut progress.progress));
+        let finished = planner.plan_steps(time_sliced.steps_per_frame.max(1));
+        let failed = planner.failed();
+        progress.progress = planner.into_progress();
+        let Some(new_plan) = finished else {
+            continue;
+        };
+        commands
+            .entity(sup_entity)
+            .remove::<(PlanningInProgress, TimeSlicedProgress<T>)>();
+        let new_plan = new_plan.for_agenda_item(progress.agenda_item.take());
+        use_background_plan(
+            sup_entity,
+            new_plan,
+            failed,
+            htn,
+            state,
+            opt_plan,
+            in_progress,
+            agenda.as_deref_mut(),
+            atr.as_ref(),
+            &mut commands,
+        );
+    }
+}
+
+/// Uses a plan that was computed from an earlier snapshot of the state, discarding it if the
+/// state has since changed to invalidate it.
+#[allow(clippy::too_many_arguments)]
+fn use_background_plan<T: HtnStateTrait>(
+    sup_entity: Entity,
+    new_plan: Plan,
+    failed: bool,
+    htn: &HTN<T>,
+    state: &T,
+    opt_plan: Option<&Plan>,
+    in_progress: &PlanningInProgress,
+    agenda: Option<&mut HtnAgenda>,
+    atr: &AppTypeRegistry,
+    commands: &mut Commands,
+) {
+    let Some(new_plan) = check_agenda_plan(sup_entity, new_plan, failed, agenda, commands) else {
+        return;
+    };
+    if !new_plan.check_validity(htn, state.clone(), atr) {
+        debug!("Discarding plan, invalidated while planning: {new_plan}");
+        commands.trigger_targets(ReplanRequest, sup_entity);
+        return;
+    }
+    use_new_plan(sup_entity, new_plan, opt_plan, commands);
+    if in_progress.replan_requested {
+        commands.trigger_targets(ReplanRequest, sup_entity);
+    }
+}
+
+/// Inserts the new plan on the supervisor, unless the existing plan is still running and is the
+/// same or higher priority.
+fn use_new_plan(
+    sup_entity: Entity,
+    mut new_plan: Plan,
+    opt_plan: Option<&Plan>,
+    commands: &mut Commands,
+) {
+    // supervisors opt in to tracing by having a PlanTrace component, which we replace with
+    // the trace of the latest planning run, even if the resulting plan is discarded.
+    if let Some(trace) = new_plan.take_trace() {
+        commands.entity(sup_entity).insert(trace);
+    }
 
     if let Some(existing_plan) = opt_plan {
-        let existing_plan_active = existing_plan.status().is_none();
-        // if existing plan is finished, we'll have to replan anyway.
-        if existing_plan_active {
+        // only a running plan competes with the new one, pending plans haven't started and
+        // finished plans are replaced regardless of their priority.
+        let existing_plan_running = existing_plan.status() == PlanStatus::Running;
+        // plans for different agenda items (or the root task) aren't comparable, and the agenda
+        // changing means the existing plan is no longer wanted.
+        let same_agenda_item = existing_plan.agenda_item() == new_plan.agenda_item();
+        if existing_plan_running && same_agenda_item {
             if *existing_plan == new_plan {
                 debug!("🔂 Plan is the same as existing, skipping");
                 return;
             }
-            // seems ok but plans that are finished need to not exist, because finished high pri
-            // plans are trumping new ones atm.
-            // need to make overall plan completion work better.
             if *existing_plan > new_plan {
-                debug!("Existing plan, which is active, has higher priority, ignoring new plan: {new_plan}");
-                // warn!("Ignored New plan: {new_plan}");
-                // warn!(
-                //     "Existing plan: {existing_plan} status: {:?}",
-                //     existing_plan.status()
-                // );
+                debug!("Existing plan, which is running, has higher priority, ignoring new plan: {new_plan}");
                 return;
             }
         }
     }
-    commands.entity(t.entity()).insert(new_plan);
+    commands.entity(sup_entity).insert(new_plan);
 }
 
-fn on_plan_added(t: Trigger<OnInsert, Plan>, mut commands: Commands, q: Query<&Plan>) {
+fn on_plan_added<T: HtnStateTrait>(
+    t: Trigger<OnInsert, Plan>,
+    mut commands: Commands,
+    mut q: Query<(
+        &mut Plan,
+        Option<&mut PlanIdCounter>,
+        &HtnSupervisor<T>,
+        &T,
+        Option<&ReplanPolicy>,
+    )>,
+    assets: Res<Assets<HtnAsset<T>>>,
+    atr: Res<AppTypeRegistry>,
+) {
     // TODO kill any children that are executing an old plan?
     // get the old plan id and kill just those children?
-    let plan = q.get(t.entity()).unwrap();
+    let Ok((mut plan, counter, htn_supervisor, state, policy)) = q.get_mut(t.entity()) else {
+        return;
+    };
+    // plans that haven't been inserted on a supervisor before get the next id
+    if let (0, Some(mut counter)) = (plan.id(), counter) {
+        plan.assign_id(counter.next());
+    }
+    // to tell whether later state changes are relevant to the plan
+    let observed = assets
+        .get(&htn_supervisor.htn_handle)
+        .filter(|_| plan.status().is_active() && policy.copied().unwrap_or_default().on_change)
+        .and_then(|asset| ObservedState::new(&plan, &asset.htn, state, atr.as_ref()));
+    match observed {
+        Some(observed) => commands.entity(t.entity()).insert(observed),
+        None => commands.entity(t.entity()).remove::<ObservedState<T>>(),
+    };
     info!("🗺️ Plan Inserted: {}", plan.task_names().join(", "));
-    commands.trigger_targets(ExecNextTask, t.entity());
+    // a retry waiting for its delay was for a task of the old plan
+    commands
+        .entity(t.entity())
+        .remove::<PendingRetry>()
+        .trigger(ExecNextTask);
 }
 
+#[allow(clippy::too_many_arguments)]
 fn on_task_complete<T: HtnStateTrait>(
     t: Trigger<TaskComplete>,
-    mut q: Query<(&mut Plan, &HtnSupervisor<T>, &mut T, &Parent)>,
+    own: OwnSupervisors<T>,
+    mut q: Query<(
+        &mut Plan,
+        &HtnSupervisor<T>,
+        &mut T,
+        &Parent,
+        Option<&mut HtnAgenda>,
+        Has<PlanRepair>,
+        Option<&ReplanPolicy>,
+    )>,
     assets: Res<Assets<HtnAsset<T>>>,
+    claims: Res<HtnClaims>,
     atr: Res<AppTypeRegistry>,
+    time: Res<Time>,
     mut commands: Commands,
 ) {
-    info!("Task complete event: {}", t.event().task_id.name());
     let TaskComplete { task_id, success } = t.event();
     let sup_entity = t.entity();
-    let Ok((mut plan, htn_sup, mut state, parent)) = q.get_mut(sup_entity) else {
+    if !own.contains(sup_entity) {
+        return;
+    }
+    info!("Task complete event: {}", task_id.name());
+    let Ok((mut plan, htn_sup, mut state, parent, mut agenda, repair, policy)) =
+        q.get_mut(sup_entity)
+    else {
         error!("HtnSupervisor {sup_entity:?} not found");
         return;
     };
@@ -220,21 +884,46 @@ fn on_task_complete<T: HtnStateTrait>(
     }
     let htn = &assets.get(htn_sup.htn_handle.id()).unwrap().htn;
     let task_name = task_id.name();
-    let Some(task) = htn.get_task_by_name(task_name) else {
+    let Some((htn_task_id, task)) = plan
+        .task(task_id)
+        .and_then(|t| Some((t.task, htn.get_task(t.task)?)))
+    else {
         error!("Task {task_id:?} not found");
         return;
     };
+    if let Task::Primitive(primitive) = task {
+        let can_retry = plan
+            .task(task_id)
+            .is_some_and(|t| t.retries < primitive.retries);
+        if !*success && can_retry {
+            let retries = plan.count_retry(task_id);
+            info!(
+                "Task {task_name} failed, retrying ({retries}/{})",
+                primitive.retries
+            );
+            let task_id = task_id.clone();
+            if primitive.retry_delay > 0.0 {
+                let at = time.elapsed_secs() + primitive.retry_delay;
+                commands
+                    .entity(sup_entity)
+                    .insert(PendingRetry { task_id, at });
+            } else {
+                commands.trigger_targets(RetryTask { task_id }, sup_entity);
+            }
+            return;
+        }
+    }
     plan.report_task_completion(task_id, *success);
 
     if *success {
         info!("Task {task_name} completed successfully -> {character_entity:?}");
         commands.trigger_targets(
-            HtnTaskEvent::Success(task_name.to_string()),
+            HtnTaskEvent::Success(HtnTaskInfo::new(&plan, task_id)),
             character_entity,
         );
     } else {
         commands.trigger_targets(
-            HtnTaskEvent::Failure(task_name.to_string()),
+            HtnTaskEvent::Failure(HtnTaskInfo::new(&plan, task_id)),
             character_entity,
         );
     }
@@ -246,103 +935,368 @@ fn on_task_complete<T: HtnStateTrait>(
                 // bypassing change detection here, any effect of a completed task will already
                 // be anticipated by the planner, no need to cause a replan.
                 primitive.apply_effects(state.bypass_change_detection(), atr.as_ref());
+                if primitive.effects.iter().any(is_world_effect) {
+                    commands.trigger_targets(HtnWorldEffects { task: htn_task_id }, sup_entity);
+                }
             }
             Task::Compound(_compound) => {}
         }
     }
 
+    if repair && plan.status() == PlanStatus::Failed {
+        match HtnPlanner::new(htn, atr.as_ref())
+            .with_claimed(claims.claimed_by_others(sup_entity))
+            .repair(&plan, task_id.index(), state.as_ref())
+        {
+            Ok(repaired) => {
+                info!("🩹 Repaired plan after {task_name} failed: {repaired}");
+                commands.entity(sup_entity).insert(repaired);
+                return;
+            }
+            Err(e) => debug!("Couldn't repair plan, replanning: {e}"),
+        }
+    }
+
+    if let (true, Some(item)) = (plan.status().is_finished(), plan.agenda_item()) {
+        finish_agenda_item(
+            sup_entity,
+            item,
+            plan.status() == PlanStatus::Succeeded,
+            agenda.as_deref_mut(),
+            &mut commands,
+        );
+    }
+
+    let replan_on_finish = policy.copied().unwrap_or_default().on_finish;
     match plan.status() {
         // plan completed successfully, let's replan.
-        Some(true) => {
-            commands.trigger_targets(ReplanRequest, sup_entity);
+        PlanStatus::Succeeded => {
+            if replan_on_finish {
+                commands.trigger_targets(ReplanRequest, sup_entity);
+            }
         }
-        // plan failed
-        Some(false) => {
-            error!("Plan failed, no more tasks will be executed. Replanning");
-            commands.trigger_targets(ReplanRequest, sup_entity);
+        PlanStatus::Failed | PlanStatus::Aborted => {
+            error!("Plan failed, no more tasks will be executed.");
+            if replan_on_finish {
+                commands.trigger_targets(ReplanRequest, sup_entity);
+            }
         }
-        // plan still pending a result
-        None => {
+        PlanStatus::Pending | PlanStatus::Running => {
             commands.trigger_targets(ExecNextTask, sup_entity);
         }
     }
 }
 
-fn on_exec_next_task<T: HtnStateTrait>(
-    t: Trigger<ExecNextTask>,
-    mut q: Query<(Option<&Children>, &Parent, &HtnSupervisor<T>, &mut Plan, &T)>,
-    q_children: Query<Entity, With<PlannedTaskId>>,
-    assets: Res<Assets<HtnAsset<T>>>,
-    type_registry: Res<AppTypeRegistry>,
-    mut commands: Commands,
-) {
-    let sup_entity = t.entity();
-    let Ok((children, parent, sup, mut plan, state)) = q.get_mut(sup_entity) else {
-        error!("HtnSupervisor not found");
-        return;
-    };
-    // kill any children executing a previous plan:
-    if let Some(children) = children {
-        for child in children.iter().filter(|c| q_children.contains(**c)) {
-            debug!("Killing child executing old plan: {child:?}");
-            commands
-                .entity(t.entity())
-                .remove_children(&[*child])
-                .remove::<PlannedTaskId>();
-            commands.entity(*child).despawn_recursive();
-        }
-    }
-    let Some(task_id) = plan.next_task_to_execute() else {
-        info!("No more tasks to execute");
-        return;
-    };
-    let htn = &assets.get(&sup.htn_handle).unwrap().htn;
-    let Some(Task::Primitive(task)) = htn.get_task_by_name(task_id.name()) else {
-        panic!("Task {task_id:?} is not a primitive on this htn");
-    };
-    if !task.preconditions_met(state, type_registry.as_ref()) {
-        debug!("Task {task_id:?} preconditions not met, failing plan - replanning.");
-        plan.abort();
-        commands.trigger_targets(ReplanRequest, sup_entity);
+fn on_exec_next_task<T: HtnStateTrait>(t: Trigger<ExecNextTask>, mut starter: TaskStarter<T>) {
+    // any operators still running are executing a previous plan
+    starter.start(
+        t.entity(),
+        |_| true,
+        |plan| {
+            let task_id = plan.next_task_to_execute();
+            if task_id.is_none() {
+                info!("No more tasks to execute");
+            }
+            task_id
+        },
+    );
+}
+
+fn on_retry_task<T: HtnStateTrait>(t: Trigger<RetryTask>, mut starter: TaskStarter<T>) {
+    let task_id = t.event().task_id.clone();
+    // the plan may have been replaced while waiting to retry, leave its operators alone
+    let still_running = starter
+        .supervisors
+        .get(t.entity())
+        .is_ok_and(|(_, _, _, plan, ..)| {
+            plan.status().is_active()
+                && plan
+                    .task(&task_id)
+                    .is_some_and(|t| t.status == TaskStatus::Running)
+        });
+    if !still_running {
+        debug!("Not retrying {task_id:?}, its plan is no longer running");
         return;
     }
+    starter.start(t.entity(), |id| *id == task_id, |_| Some(task_id.clone()));
+}
+
+/// Spawns the operators that execute a supervisor's tasks.
+#[derive(SystemParam)]
+struct TaskStarter<'w, 's, T: HtnStateTrait> {
+    supervisors: Query<
+        'w,
+        's,
+        (
+            Option<&'static Children>,
+            &'static Parent,
+            &'static HtnSupervisor<T>,
+            &'static mut Plan,
+            &'static T,
+            Option<&'static DefaultTaskTimeout>,
+            Option<&'static ReplanPolicy>,
+        ),
+    >,
+    own: OwnSupervisors<'w, 's, T>,
+    operators: Query<'w, 's, &'static PlannedTaskId>,
+    assets: Res<'w, Assets<HtnAsset<T>>>,
+    type_registry: Res<'w, AppTypeRegistry>,
+    time: Res<'w, Time>,
+    commands: Commands<'w, 's>,
+}
 
-    let task_strategy = task.execution_command(state, &type_registry.read(), &task_id);
-    match task_strategy {
-        TaskExecutionStrategy::BehaviourTree { tree, task_id } => {
-            let task_name = task_id.name().to_string();
-            // warn!("Executing operator: {task_name}");
-            let character_entity = parent.get();
-            commands
-                .spawn((
+impl<T: HtnStateTrait> TaskStarter<'_, '_, T> {
+    /// Kills the supervisor's operators for the tasks `kill` matches, then spawns the operator
+    /// for the task `choose_task` picks from its plan. If the task's preconditions aren't met,
+    /// the plan is aborted and we replan.
+    fn start(
+        &mut self,
+        sup_entity: Entity,
+        kill: impl Fn(&PlannedTaskId) -> bool,
+        choose_task: impl FnOnce(&mut Plan) -> Option<PlannedTaskId>,
+    ) {
+        if !self.own.contains(sup_entity) {
+            return;
+        }
+        let Ok((children, parent, sup, mut plan, state, default_timeout, policy)) =
+            self.supervisors.get_mut(sup_entity)
+        else {
+            error!("HtnSupervisor not found");
+            return;
+        };
+        // kill any children executing a previous plan, or a failed attempt at this task:
+        if let Some(children) = children {
+            for child in children
+                .iter()
+                .filter(|c| self.operators.get(**c).is_ok_and(&kill))
+            {
+                debug!("Killing child executing old plan: {child:?}");
+                self.commands
+                    .entity(sup_entity)
+                    .remove_children(&[*child])
+                    .remove::<PlannedTaskId>();
+                self.commands.entity(*child).despawn_recursive();
+            }
+        }
+        let Some(task_id) = choose_task(&mut plan) else {
+            return;
+        };
+        let htn = &self.assets.get(&sup.htn_handle).unwrap().htn;
+        let Some(Task::Primitive(task)) = plan.task(&task_id).and_then(|t| htn.get_task(t.task))
+        else {
+            panic!("Task {task_id:?} is not a primitive on this htn");
+        };
+        if !task.preconditions_met(state, self.type_registry.as_ref()) {
+            debug!("Task {task_id:?} preconditions not met, failing plan.");
+            plan.abort();
+            if policy
+                .copied()
+                .unwrap_or_default()
+                .replans_on_invalidation()
+            {
+                self.commands.trigger_targets(ReplanRequest, sup_entity);
+            }
+            return;
+        }
+
+        let estimate = plan.task(&task_id).and_then(|t| t.duration);
+        let timeout = task.timeout.or_else(|| {
+            let default_timeout = default_timeout?;
+            if default_timeout.secs().is_none() {
+                warn!(
+                    "Ignoring DefaultTaskTimeout of {}s, it must be positive",
+                    default_timeout.0
+                );
+            }
+            default_timeout.secs()
+        });
+        let task_strategy = task.execution_command(state, &self.type_registry.read(), &task_id);
+        match task_strategy {
+            TaskExecutionStrategy::BehaviourTree { tree, task_id } => {
+                let task_info = HtnTaskInfo::new(&plan, &task_id);
+                // warn!("Executing operator: {task_name}");
+                let character_entity = parent.get();
+                let mut operator = self.commands.spawn((
                     task_id,
                     BehaveTree::new(tree),
                     BehaveTargetEntity::Entity(character_entity),
-                    BehaveSupervisorEntity(t.entity()),
-                ))
-                .set_parent(t.entity());
-            commands.trigger_targets(HtnTaskEvent::Executing(task_name), character_entity);
+                    BehaveSupervisorEntity(sup_entity),
+                ));
+                operator.set_parent(sup_entity);
+                if estimate.is_some() || timeout.is_some() {
+                    operator.insert(TaskTimer {
+                        started: self.time.elapsed_secs(),
+                        estimate,
+                        timeout,
+                        warned: false,
+                    });
+                }
+                self.commands
+                    .trigger_targets(HtnTaskEvent::Executing(task_info), character_entity);
+            }
+        }
+    }
+}
+
+/// Warn when a task takes this many times longer than its estimated duration.
+const OVERRUN_WARNING_FACTOR: f32 = 2.0;
+
+/// On operator entities for tasks with an estimated duration or a timeout, to warn when they
+/// overrun, and fail them when they time out.
+#[derive(Component)]
+struct TaskTimer {
+    started: f32,
+    estimate: Option<f32>,
+    timeout: Option<f32>,
+    warned: bool,
+}
+
+fn warn_overrunning_tasks(
+    mut q: Query<(&PlannedTaskId, &mut TaskTimer), Without<BehaveFinished>>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_secs();
+    for (task_id, mut timer) in q.iter_mut() {
+        let Some(estimate) = timer.estimate else {
+            continue;
+        };
+        let elapsed = now - timer.started;
+        if timer.warned || elapsed <= estimate * OVERRUN_WARNING_FACTOR {
+            continue;
+        }
+        warn!(
+            "Task {} has been running for {elapsed:.1}s, estimated duration was {estimate:.1}s",
+            task_id.name(),
+        );
+        timer.warned = true;
+    }
+}
+
+/// Despawns the operators of tasks that have been executing for longer than their timeout, and
+/// fails the tasks.
+fn time_out_tasks(
+    q: Query<(Entity, &PlannedTaskId, &TaskTimer, &Parent), Without<BehaveFinished>>,
+    q_sup: Query<(&Parent, &Plan)>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    let now = time.elapsed_secs();
+    for (entity, task_id, timer, parent) in q.iter() {
+        let Some(timeout) = timer.timeout else {
+            continue;
+        };
+        if now - timer.started <= timeout {
+            continue;
         }
+        warn!("Task {} timed out after {timeout:.1}s", task_id.name());
+        let sup_entity = parent.get();
+        commands.entity(sup_entity).remove_children(&[entity]);
+        commands.entity(entity).despawn_recursive();
+        if let Ok((character, plan)) = q_sup.get(sup_entity) {
+            commands.trigger_targets(
+                HtnTaskEvent::TimedOut(HtnTaskInfo::new(plan, task_id)),
+                character.get(),
+            );
+        }
+        commands.trigger_targets(TaskComplete::new(task_id.clone(), false), sup_entity);
     }
 }
 
 /// Event triggered on character entity when a task starts or completes.
 #[derive(Event, Debug, Clone, Reflect)]
 pub enum HtnTaskEvent {
-    Executing(String),
-    Success(String),
-    Failure(String),
+    Executing(HtnTaskInfo),
+    Success(HtnTaskInfo),
+    Failure(HtnTaskInfo),
+    /// The task was still executing when its timeout expired, it's also reported as a failure.
+    TimedOut(HtnTaskInfo),
+}
+
+impl HtnTaskEvent {
+    pub fn task(&self) -> &HtnTaskInfo {
+        match self {
+            Self::Executing(task)
+            | Self::Success(task)
+            | Self::Failure(task)
+            | Self::TimedOut(task) => task,
+        }
+    }
+}
+
+/// The primitive task an [`HtnTaskEvent`] is for, and the compound task it was decomposed from,
+/// see [`PlanTree::parent_compound`].
+#[derive(Debug, Clone, PartialEq, Reflect)]
+pub struct HtnTaskInfo {
+    pub name: String,
+    /// The compound task's name, `None` if planning started with this task, or the plan has no
+    /// decomposition tree.
+    pub parent: Option<String>,
+    /// The index of the method chosen for the parent, and its name if it has one.
+    pub method_index: Option<usize>,
+    pub method_name: Option<String>,
+}
+
+impl HtnTaskInfo {
+    fn new(plan: &Plan, task_id: &PlannedTaskId) -> Self {
+        let parent = plan.tree().parent_compound(task_id.index());
+        let (method_index, method_name) = match parent.map(|p| &p.kind) {
+            Some(PlanNodeKind::Compound {
+                method_index,
+                method_name,
+            }) => (Some(*method_index), method_name.clone()),
+            _ => (None, None),
+        };
+        Self {
+            name: task_id.name().to_string(),
+            parent: parent.map(|p| p.name.clone()),
+            method_index,
+            method_name,
+        }
+    }
 }
 
 #[derive(Event)]
 struct ExecNextTask;
 
-fn task_finished(
+/// Starts a task that failed again, see `PrimitiveTask::retries`.
+#[derive(Event)]
+struct RetryTask {
+    task_id: PlannedTaskId,
+}
+
+/// On a supervisor entity waiting for a task's `retry_delay` before retrying it.
+#[derive(Component)]
+struct PendingRetry {
+    task_id: PlannedTaskId,
+    at: f32,
+}
+
+fn start_pending_retries(
+    q: Query<(Entity, &PendingRetry)>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    let now = time.elapsed_secs();
+    for (sup_entity, retry) in q.iter().filter(|(_, retry)| retry.at <= now) {
+        commands.entity(sup_entity).remove::<PendingRetry>();
+        commands.trigger_targets(
+            RetryTask {
+                task_id: retry.task_id.clone(),
+            },
+            sup_entity,
+        );
+    }
+}
+
+fn task_finished<T: HtnStateTrait>(
     q: Query<(&BehaveFinished, &PlannedTaskId, &Parent), Added<BehaveFinished>>,
-    // mut q_sup: Query<(&mut Plan, &HtnSupervisor<T>)>,
+    own: OwnSupervisors<T>,
     mut commands: Commands,
 ) {
     for (finished, task_id, parent) in q.iter() {
+        if !own.contains(parent.get()) {
+            continue;
+        }
         commands.trigger_targets(
             TaskComplete {
                 task_id: task_id.clone(),
@@ -1,4 +1,4 @@
-use crate::HtnStateTrait;
+use crate::{claims::ClaimKey, HtnStateTrait};
 
 use super::*;
 use crate::error::HtnErr;
@@ -6,6 +6,7 @@ use bevy::{
     prelude::*,
     reflect::{DynamicEnum, DynamicVariant, VariantInfo},
 };
+use std::{any::TypeId, cmp::Ordering};
 
 #[derive(Clone, Debug, Reflect, PartialEq)]
 pub enum HtnCondition {
@@ -81,6 +82,9 @@ pub enum HtnCondition {
         notted: bool,
         syntax: String,
     },
+    /// The value of the field isn't claimed by another supervisor, see `HtnClaims`.
+    /// The planner checks this against the claims, it always holds against the state alone.
+    Unclaimed { field: String, syntax: String },
 }
 
 impl HtnCondition {
@@ -98,6 +102,34 @@ impl HtnCondition {
             HtnCondition::EqualsFloat { syntax, .. } => syntax.clone(),
             HtnCondition::GreaterThanFloat { syntax, .. } => syntax.clone(),
             HtnCondition::LessThanFloat { syntax, .. } => syntax.clone(),
+            HtnCondition::Unclaimed { syntax, .. } => syntax.clone(),
+        }
+    }
+    /// The name of the state field this condition tests.
+    pub fn field(&self) -> &str {
+        match self {
+            HtnCondition::EqualsBool { field, .. } => field,
+            HtnCondition::GreaterThanInt { field, .. } => field,
+            HtnCondition::GreaterThanIdentifier { field, .. } => field,
+            HtnCondition::LessThanInt { field, .. } => field,
+            HtnCondition::LessThanIdentifier { field, .. } => field,
+            HtnCondition::EqualsEnum { field, .. } => field,
+            HtnCondition::EqualsInt { field, .. } => field,
+            HtnCondition::EqualsIdentifier { field, .. } => field,
+            HtnCondition::EqualsNone { field, .. } => field,
+            HtnCondition::EqualsFloat { field, .. } => field,
+            HtnCondition::GreaterThanFloat { field, .. } => field,
+            HtnCondition::LessThanFloat { field, .. } => field,
+            HtnCondition::Unclaimed { field, .. } => field,
+        }
+    }
+    /// The name of the field this condition compares against, for conditions between two fields.
+    pub fn other_field(&self) -> Option<&str> {
+        match self {
+            HtnCondition::GreaterThanIdentifier { other_field, .. }
+            | HtnCondition::LessThanIdentifier { other_field, .. }
+            | HtnCondition::EqualsIdentifier { other_field, .. } => Some(other_field),
+            _ => None,
         }
     }
     fn verify_field_type<FieldType: 'static>(
@@ -105,7 +137,7 @@ impl HtnCondition {
         field: &str,
         syntax: &str,
     ) -> Result<(), HtnErr> {
-        let Some(val) = state_struct.field(field) else {
+        let Some(val) = state_field(state_struct, field) else {
             return Err(HtnErr::Condition {
                 syntax: syntax.to_string(),
                 details: format!("Unknown state field `{field}` for condition `{syntax}`"),
@@ -138,6 +170,23 @@ impl HtnCondition {
             HtnCondition::EqualsBool { field, syntax, .. } => {
                 Self::verify_field_type::<bool>(reflected, field, syntax)
             }
+            HtnCondition::Unclaimed { field, syntax } => {
+                let Some(val) = state_field(reflected, field) else {
+                    return Err(HtnErr::Condition {
+                        syntax: syntax.to_string(),
+                        details: format!("Unknown state field `{field}` for condition `{syntax}`"),
+                    });
+                };
+                if !ClaimKey::is_claimable(val) {
+                    return Err(HtnErr::Condition {
+                        syntax: syntax.to_string(),
+                        details: format!(
+                            "State field `{field}` can't be claimed, its value can't be hashed"
+                        ),
+                    });
+                }
+                Ok(())
+            }
             HtnCondition::GreaterThanInt { field, syntax, .. } => {
                 Self::verify_field_type::<i32>(reflected, field, syntax)
             }
@@ -157,7 +206,7 @@ impl HtnCondition {
                 Self::verify_field_type::<f32>(reflected, field, syntax)
             }
             HtnCondition::EqualsNone { field, syntax, .. } => {
-                if let Some(val) = reflected.field(field) {
+                if let Some(val) = state_field(reflected, field) {
                     let dyn_enum = val.reflect_ref().as_enum().map_err(|_| HtnErr::Enum {
                         syntax: syntax.to_string(),
                         details: format!(
@@ -198,7 +247,7 @@ impl HtnCondition {
                 syntax,
                 ..
             } => {
-                if let Some(state_val) = reflected.field(field) {
+                if let Some(state_val) = state_field(reflected, field) {
                     let dyn_enum = state_val.reflect_ref().as_enum().map_err(|_| {
                         HtnErr::Enum {
                             syntax: syntax.to_string(),
@@ -266,13 +315,13 @@ impl HtnCondition {
                 syntax,
                 ..
             } => {
-                let Some(val1) = reflected.field(field1) else {
+                let Some(val1) = state_field(reflected, field1) else {
                     return Err(HtnErr::Condition {
                         syntax: syntax.to_string(),
                         details: format!("Unknown state field `{field1}` for condition `{syntax}`"),
                     });
                 };
-                let Some(val2) = reflected.field(field2) else {
+                let Some(val2) = state_field(reflected, field2) else {
                     return Err(HtnErr::Condition {
                         syntax: syntax.to_string(),
                         details: format!("Unknown state field `{field2}` for condition `{syntax}`"),
@@ -291,6 +340,16 @@ impl HtnCondition {
                         ),
                     });
                 }
+                if !matches!(self, HtnCondition::EqualsIdentifier { .. })
+                    && field_ordering(val1).is_none()
+                {
+                    return Err(HtnErr::Condition {
+                        syntax: syntax.to_string(),
+                        details: format!(
+                            "Fields `{field1}` and `{field2}` can't be ordered, only numbers can be, for condition `{syntax}`"
+                        ),
+                    });
+                }
                 Ok(())
             }
         }
@@ -307,7 +366,7 @@ impl HtnCondition {
                 notted,
                 ..
             } => {
-                if let Some(val) = reflected.field(field) {
+                if let Some(val) = state_field(reflected, field) {
                     if let Some(b) = val.try_downcast_ref::<bool>() {
                         if *notted {
                             *b != *value
@@ -327,7 +386,7 @@ impl HtnCondition {
                 notted,
                 ..
             } => {
-                if let Some(val) = reflected.field(field) {
+                if let Some(val) = state_field(reflected, field) {
                     if let Some(f) = val.try_downcast_ref::<f32>() {
                         if *notted {
                             *f != *value
@@ -347,7 +406,7 @@ impl HtnCondition {
                 orequals,
                 ..
             } => {
-                if let Some(val) = reflected.field(field) {
+                if let Some(val) = state_field(reflected, field) {
                     if let Some(f) = val.try_downcast_ref::<f32>() {
                         if *orequals {
                             *f >= *threshold
@@ -367,12 +426,12 @@ impl HtnCondition {
                 orequals,
                 ..
             } => {
-                if let Some(val) = reflected.field(field) {
+                if let Some(val) = state_field(reflected, field) {
                     if let Some(i) = val.try_downcast_ref::<f32>() {
                         if *orequals {
-                            *i >= *threshold
+                            *i <= *threshold
                         } else {
-                            *i > *threshold
+                            *i < *threshold
                         }
                     } else {
                         false
@@ -387,7 +446,7 @@ impl HtnCondition {
                 orequals,
                 ..
             } => {
-                if let Some(val) = reflected.field(field) {
+                if let Some(val) = state_field(reflected, field) {
                     if let Some(i) = val.try_downcast_ref::<i32>() {
                         if *orequals {
                             *i <= *threshold
@@ -407,7 +466,7 @@ impl HtnCondition {
                 orequals,
                 ..
             } => {
-                if let Some(val) = reflected.field(field) {
+                if let Some(val) = state_field(reflected, field) {
                     if let Some(f) = val.try_downcast_ref::<i32>() {
                         if *orequals {
                             *f >= *threshold
@@ -427,7 +486,7 @@ impl HtnCondition {
                 notted,
                 ..
             } => {
-                if let Some(val) = reflected.field(field) {
+                if let Some(val) = state_field(reflected, field) {
                     if let Some(i) = val.try_downcast_ref::<i32>() {
                         if *notted {
                             *i != *value
@@ -449,7 +508,7 @@ impl HtnCondition {
                 ..
             } => {
                 // https://github.com/makspll/bevy_mod_scripting/blob/a4d1ffbcae98f42393ab447d73efe9b0b543426f/crates/bevy_mod_scripting_core/src/bindings/world.rs#L642
-                if let Some(val) = reflected.field(field) {
+                if let Some(val) = state_field(reflected, field) {
                     let dyn_enum = val.reflect_ref().as_enum().expect("Field is not an enum");
                     let enum_info = dyn_enum
                         .get_represented_enum_info()
@@ -477,11 +536,15 @@ impl HtnCondition {
             HtnCondition::EqualsIdentifier {
                 field: field1,
                 other_field: field2,
+                notted,
                 ..
             } => {
-                if let (Some(val1), Some(val2)) = (reflected.field(field1), reflected.field(field2))
-                {
-                    val1.reflect_partial_eq(val2).unwrap_or(false)
+                if let (Some(val1), Some(val2)) = (
+                    state_field(reflected, field1),
+                    state_field(reflected, field2),
+                ) {
+                    val1.reflect_partial_eq(val2)
+                        .is_some_and(|equal| equal != *notted)
                 } else {
                     false
                 }
@@ -500,17 +563,17 @@ impl HtnCondition {
                 syntax,
                 ..
             } => {
-                let Some(val1) = reflected.field(field1) else {
+                let Some(val1) = state_field(reflected, field1) else {
                     return false;
                 };
-                let Some(val2) = reflected.field(field2) else {
+                let Some(val2) = state_field(reflected, field2) else {
                     return false;
                 };
                 let type_id = val1
                     .get_represented_type_info()
                     .map(|inf| inf.type_id())
                     .unwrap();
-                if val1
+                if val2
                     .get_represented_type_info()
                     .map(|inf| inf.type_id())
                     .unwrap()
@@ -520,29 +583,17 @@ impl HtnCondition {
                     return false;
                 }
                 // don't know how to dynamically do this, there isn't a ReflectPartialOrd.
-                // so for now i'll just support numbers.
-                let type_path = val1
-                    .get_represented_type_info()
-                    .unwrap()
-                    .type_path_table()
-                    .short_path();
-                let ordering = match type_path {
-                    "i32" => val1.try_downcast_ref::<i32>().unwrap().partial_cmp(val2.try_downcast_ref::<i32>().unwrap()).unwrap(),
-                    // not supported by effects (yet?) but could still be used in conditions:
-                    "i8" => val1.try_downcast_ref::<i8>().unwrap().partial_cmp(val2.try_downcast_ref::<i8>().unwrap()).unwrap(),
-                    "i16" => val1.try_downcast_ref::<i16>().unwrap().partial_cmp(val2.try_downcast_ref::<i16>().unwrap()).unwrap(),
-                    "i64" => val1.try_downcast_ref::<i64>().unwrap().partial_cmp(val2.try_downcast_ref::<i64>().unwrap()).unwrap(),
-                    "i128" => val1.try_downcast_ref::<i128>().unwrap().partial_cmp(val2.try_downcast_ref::<i128>().unwrap()).unwrap(),
-                    // f
-                    "f32" => val1.try_downcast_ref::<f32>().unwrap().partial_cmp(val2.try_downcast_ref::<f32>().unwrap()).unwrap(),
-                    "f64" => val1.try_downcast_ref::<f64>().unwrap().partial_cmp(val2.try_downcast_ref::<f64>().unwrap()).unwrap(),
-                    // u
-                    "u8" => val1.try_downcast_ref::<u8>().unwrap().partial_cmp(val2.try_downcast_ref::<u8>().unwrap()).unwrap(),
-                    "u16" => val1.try_downcast_ref::<u16>().unwrap().partial_cmp(val2.try_downcast_ref::<u16>().unwrap()).unwrap(),
-                    "u32" => val1.try_downcast_ref::<u32>().unwrap().partial_cmp(val2.try_downcast_ref::<u32>().unwrap()).unwrap(),
-                    "u64" => val1.try_downcast_ref::<u64>().unwrap().partial_cmp(val2.try_downcast_ref::<u64>().unwrap()).unwrap(),
-                    "u128" => val1.try_downcast_ref::<u128>().unwrap().partial_cmp(val2.try_downcast_ref::<u128>().unwrap()).unwrap(),
-                    _ => unimplemented!("GreaterThanIdentifier | LessThanIdentifier not implemented for type: {type_path} for condition: `{syntax}`"),
+                // so for now i'll just support numbers, verify_types rejects anything else.
+                let Some(compare) = field_ordering(val1) else {
+                    warn!(
+                        "Can't order fields of type {} for condition: `{syntax}`",
+                        val1.reflect_short_type_path()
+                    );
+                    return false;
+                };
+                // NaNs can't be ordered
+                let Some(ordering) = compare(val1, val2) else {
+                    return false;
                 };
 
                 match self {
@@ -566,7 +617,7 @@ impl HtnCondition {
                 }
             }
             HtnCondition::EqualsNone { field, notted, .. } => {
-                if let Some(val) = reflected.field(field) {
+                if let Some(val) = state_field(reflected, field) {
                     let dyn_enum = val
                         .reflect_ref()
                         .as_enum()
@@ -591,6 +642,348 @@ impl HtnCondition {
                     false
                 }
             }
+            HtnCondition::Unclaimed { .. } => true,
+        }
+    }
+    /// Resolves this condition against the state type, returning None if the fields it uses don't
+    /// exist or have unexpected types (which `verify_types` reports as errors).
+    pub fn compile<T: HtnStateTrait>(&self, state: &T) -> Option<CompiledCondition> {
+        let reflected = state.reflect_ref().as_struct().ok()?;
+        let compiled = match self {
+            HtnCondition::EqualsBool {
+                field,
+                value,
+                notted,
+                ..
+            } => CompiledCondition::Bool {
+                field: FieldPath::resolve_typed::<bool>(reflected, field)?,
+                value: *value,
+                cmp: Comparison::equality(*notted),
+            },
+            HtnCondition::EqualsInt {
+                field,
+                value,
+                notted,
+                ..
+            } => CompiledCondition::Int {
+                field: FieldPath::resolve_typed::<i32>(reflected, field)?,
+                value: *value,
+                cmp: Comparison::equality(*notted),
+            },
+            HtnCondition::GreaterThanInt {
+                field,
+                threshold,
+                orequals,
+                ..
+            } => CompiledCondition::Int {
+                field: FieldPath::resolve_typed::<i32>(reflected, field)?,
+                value: *threshold,
+                cmp: Comparison::greater(*orequals),
+            },
+            HtnCondition::LessThanInt {
+                field,
+                threshold,
+                orequals,
+                ..
+            } => CompiledCondition::Int {
+                field: FieldPath::resolve_typed::<i32>(reflected, field)?,
+                value: *threshold,
+                cmp: Comparison::less(*orequals),
+            },
+            HtnCondition::EqualsFloat {
+                field,
+                value,
+                notted,
+                ..
+            } => CompiledCondition::Float {
+                field: FieldPath::resolve_typed::<f32>(reflected, field)?,
+                value: *value,
+                cmp: Comparison::equality(*notted),
+            },
+            HtnCondition::GreaterThanFloat {
+                field,
+                threshold,
+                orequals,
+                ..
+            } => CompiledCondition::Float {
+                field: FieldPath::resolve_typed::<f32>(reflected, field)?,
+                value: *threshold,
+                cmp: Comparison::greater(*orequals),
+            },
+            HtnCondition::LessThanFloat {
+                field,
+                threshold,
+                orequals,
+                ..
+            } => CompiledCondition::Float {
+                field: FieldPath::resolve_typed::<f32>(reflected, field)?,
+                value: *threshold,
+                cmp: Comparison::less(*orequals),
+            },
+            HtnCondition::EqualsEnum {
+                field,
+                enum_type,
+                enum_variant,
+                notted,
+                ..
+            } => {
+                let path = FieldPath::resolve(reflected, field)?;
+                let enum_info = path
+                    .get(reflected)?
+                    .reflect_ref()
+                    .as_enum()
+                    .ok()?
+                    .get_represented_enum_info()?;
+                if enum_info.type_path_table().ident() != Some(enum_type) {
+                    return None;
+                }
+                let VariantInfo::Unit(_) = enum_info.variant(enum_variant)? else {
+                    return None;
+                };
+                CompiledCondition::Variant {
+                    field: path,
+                    variant: enum_info.index_of(enum_variant)?,
+                    notted: *notted,
+                }
+            }
+            HtnCondition::EqualsNone { field, notted, .. } => {
+                let path = FieldPath::resolve(reflected, field)?;
+                let enum_info = path
+                    .get(reflected)?
+                    .reflect_ref()
+                    .as_enum()
+                    .ok()?
+                    .get_represented_enum_info()?;
+                if enum_info.variant_names() != ["None", "Some"] {
+                    return None;
+                }
+                CompiledCondition::Variant {
+                    field: path,
+                    variant: enum_info.index_of("None")?,
+                    notted: *notted,
+                }
+            }
+            HtnCondition::EqualsIdentifier {
+                field,
+                other_field,
+                notted,
+                ..
+            } => {
+                let path = FieldPath::resolve(reflected, field)?;
+                let other_path = FieldPath::resolve(reflected, other_field)?;
+                let val = path.get(reflected)?;
+                if represented_type_id(val)? != represented_type_id(other_path.get(reflected)?)? {
+                    return None;
+                }
+                CompiledCondition::Fields {
+                    field: path,
+                    other_field: other_path,
+                    // fields that can't be ordered are compared with reflect_partial_eq
+                    ordering: field_ordering(val).unwrap_or(reflect_equality),
+                    cmp: Comparison::equality(*notted),
+                }
+            }
+            HtnCondition::GreaterThanIdentifier {
+                field,
+                other_field,
+                orequals,
+                ..
+            }
+            | HtnCondition::LessThanIdentifier {
+                field,
+                other_field,
+                orequals,
+                ..
+            } => {
+                let path = FieldPath::resolve(reflected, field)?;
+                let other_path = FieldPath::resolve(reflected, other_field)?;
+                let val = path.get(reflected)?;
+                if represented_type_id(val)? != represented_type_id(other_path.get(reflected)?)? {
+                    return None;
+                }
+                let cmp = if matches!(self, HtnCondition::GreaterThanIdentifier { .. }) {
+                    Comparison::greater(*orequals)
+                } else {
+                    Comparison::less(*orequals)
+                };
+                CompiledCondition::Fields {
+                    field: path,
+                    other_field: other_path,
+                    ordering: field_ordering(val)?,
+                    cmp,
+                }
+            }
+            HtnCondition::Unclaimed { .. } => CompiledCondition::Unclaimed,
+        };
+        Some(compiled)
+    }
+}
+
+/// Compares two state fields of the same type, returning None if they can't be compared.
+pub type FieldOrdering = fn(&dyn PartialReflect, &dyn PartialReflect) -> Option<Ordering>;
+
+fn ordering<N: PartialOrd + 'static>(
+    a: &dyn PartialReflect,
+    b: &dyn PartialReflect,
+) -> Option<Ordering> {
+    a.try_downcast_ref::<N>()?
+        .partial_cmp(b.try_downcast_ref::<N>()?)
+}
+
+/// Only distinguishes equal from not equal, for field types without an ordering.
+fn reflect_equality(a: &dyn PartialReflect, b: &dyn PartialReflect) -> Option<Ordering> {
+    a.reflect_partial_eq(b)?
+        .then_some(Ordering::Equal)
+        .or(Some(Ordering::Less))
+}
+
+/// Returns a typed ordering function for numeric fields.
+fn field_ordering(val: &dyn PartialReflect) -> Option<FieldOrdering> {
+    let type_id = represented_type_id(val)?;
+    let orderings: [(TypeId, FieldOrdering); 12] = [
+        (TypeId::of::<i32>(), ordering::<i32>),
+        // not supported by effects (yet?) but could still be used in conditions:
+        (TypeId::of::<i8>(), ordering::<i8>),
+        (TypeId::of::<i16>(), ordering::<i16>),
+        (TypeId::of::<i64>(), ordering::<i64>),
+        (TypeId::of::<i128>(), ordering::<i128>),
+        (TypeId::of::<f32>(), ordering::<f32>),
+        (TypeId::of::<f64>(), ordering::<f64>),
+        (TypeId::of::<u8>(), ordering::<u8>),
+        (TypeId::of::<u16>(), ordering::<u16>),
+        (TypeId::of::<u32>(), ordering::<u32>),
+        (TypeId::of::<u64>(), ordering::<u64>),
+        (TypeId::of::<u128>(), ordering::<u128>),
+    ];
+    orderings
+        .into_iter()
+        .find(|(id, _)| *id == type_id)
+        .map(|(_, ordering)| ordering)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl Comparison {
+    fn equality(notted: bool) -> Self {
+        if notted {
+            Comparison::NotEqual
+        } else {
+            Comparison::Equal
+        }
+    }
+
+    fn greater(orequals: bool) -> Self {
+        if orequals {
+            Comparison::GreaterOrEqual
+        } else {
+            Comparison::Greater
+        }
+    }
+
+    fn less(orequals: bool) -> Self {
+        if orequals {
+            Comparison::LessOrEqual
+        } else {
+            Comparison::Less
+        }
+    }
+
+    /// Whether the ordering of field to value satisfies this comparison.
+    /// Values that can't be compared only satisfy NotEqual.
+    pub fn holds(self, ordering: Option<Ordering>) -> bool {
+        match self {
+            Comparison::Equal => ordering == Some(Ordering::Equal),
+            Comparison::NotEqual => ordering != Some(Ordering::Equal),
+            Comparison::Greater => ordering == Some(Ordering::Greater),
+            Comparison::GreaterOrEqual => {
+                matches!(ordering, Some(Ordering::Greater | Ordering::Equal))
+            }
+            Comparison::Less => ordering == Some(Ordering::Less),
+            Comparison::LessOrEqual => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        }
+    }
+}
+
+/// A condition resolved against the state type by [`HtnCondition::compile`].
+///
+/// Fields are referred to by their indices in the state struct, so evaluating doesn't need to look
+/// them up by name or consult the type registry.
+#[derive(Clone, Debug)]
+pub enum CompiledCondition {
+    Bool {
+        field: FieldPath,
+        value: bool,
+        cmp: Comparison,
+    },
+    Int {
+        field: FieldPath,
+        value: i32,
+        cmp: Comparison,
+    },
+    Float {
+        field: FieldPath,
+        value: f32,
+        cmp: Comparison,
+    },
+    /// Compares the variant index of a unit enum field, or an Option against None.
+    Variant {
+        field: FieldPath,
+        variant: usize,
+        notted: bool,
+    },
+    Fields {
+        field: FieldPath,
+        other_field: FieldPath,
+        ordering: FieldOrdering,
+        cmp: Comparison,
+    },
+    /// Claims are checked by the planner, so this always holds.
+    Unclaimed,
+}
+
+impl CompiledCondition {
+    pub fn evaluate(&self, state: &dyn Struct) -> bool {
+        fn compare<F: PartialOrd + 'static>(
+            state: &dyn Struct,
+            field: &FieldPath,
+            value: &F,
+            cmp: Comparison,
+        ) -> bool {
+            field
+                .get(state)
+                .and_then(|val| val.try_downcast_ref::<F>())
+                .is_some_and(|val| cmp.holds(val.partial_cmp(value)))
+        }
+        match self {
+            CompiledCondition::Bool { field, value, cmp } => compare(state, field, value, *cmp),
+            CompiledCondition::Int { field, value, cmp } => compare(state, field, value, *cmp),
+            CompiledCondition::Float { field, value, cmp } => compare(state, field, value, *cmp),
+            CompiledCondition::Variant {
+                field,
+                variant,
+                notted,
+            } => field
+                .get(state)
+                .and_then(|val| val.reflect_ref().as_enum().ok())
+                .is_some_and(|val| (val.variant_index() == *variant) != *notted),
+            CompiledCondition::Fields {
+                field,
+                other_field,
+                ordering,
+                cmp,
+            } => match (field.get(state), other_field.get(state)) {
+                (Some(a), Some(b)) => cmp.holds(ordering(a, b)),
+                _ => false,
+            },
+            CompiledCondition::Unclaimed => true,
         }
     }
 }
@@ -601,6 +994,27 @@ mod tests {
 
     use super::*;
 
+    #[derive(Reflect, Default, Clone, Debug, PartialEq, Eq)]
+    #[reflect(Default)]
+    enum Location {
+        #[default]
+        Home,
+        Other,
+        Park,
+    }
+
+    #[derive(Reflect, Resource, Clone, Debug, Default, Component)]
+    #[reflect(Default, Resource)]
+    struct State {
+        energy: i32,
+        floatyness: f32,
+        happy: bool,
+        location: Location,
+        e1: i32,
+        e2: i32,
+        optfloat: Option<f32>,
+    }
+
     #[test]
     fn test_conditions() {
         {
@@ -609,27 +1023,6 @@ mod tests {
             app.add_plugins(bevy::log::LogPlugin::default());
         }
 
-        #[derive(Reflect, Default, Clone, Debug, PartialEq, Eq)]
-        #[reflect(Default)]
-        enum Location {
-            #[default]
-            Home,
-            Other,
-            Park,
-        }
-
-        #[derive(Reflect, Resource, Clone, Debug, Default, Component)]
-        #[reflect(Default, Resource)]
-        struct State {
-            energy: i32,
-            floatyness: f32,
-            happy: bool,
-            location: Location,
-            e1: i32,
-            e2: i32,
-            optfloat: Option<f32>,
-        }
-
         let src = r#"
             schema {
                 version: 0.1.0
@@ -844,5 +1237,142 @@ mod tests {
         };
         assert!(!condition.evaluate(&state, &atr));
         assert!(condition.evaluate(&state2, &atr));
+
+        // compiled conditions must agree with evaluating via reflection.
+        let src = r#"
+            schema {
+                version: 0.1.0
+            }
+            primitive_task "Compiled Conditions Test" {
+                operator: DummyOperator
+                preconditions: [
+                    happy == false,
+                    happy != false,
+                    energy == 10,
+                    energy != 10,
+                    energy > 10,
+                    energy >= 10,
+                    energy < 10,
+                    energy <= 10,
+                    floatyness == 2.0,
+                    floatyness > 1.0,
+                    floatyness < 4.0,
+                    floatyness <= 1.0,
+                    location == Location::Home,
+                    location != Location::Park,
+                    e1 == e2,
+                    e1 != e2,
+                    e1 > e2,
+                    e1 < e2,
+                    e1 >= e2,
+                    e1 <= e2,
+                    optfloat == None,
+                    optfloat != None,
+                ]
+                effects: []
+            }
+            "#;
+        let htn = parse_htn::<State>(src).expect("Failed to parse htn");
+        let Some(Task::Primitive(pt)) = &htn.tasks.first() else {
+            panic!("Task should exist");
+        };
+        assert_eq!(pt.preconditions.len(), 22);
+        let state3 = State {
+            location: Location::Park,
+            e1: 2,
+            energy: 11,
+            ..state2.clone()
+        };
+        for cond in pt.preconditions.iter() {
+            let compiled = cond
+                .compile(&state)
+                .unwrap_or_else(|| panic!("Condition should compile: {}", cond.syntax()));
+            for s in [&state, &state2, &state3] {
+                assert_eq!(
+                    compiled.evaluate(s.reflect_ref().as_struct().unwrap()),
+                    cond.evaluate(s, &atr),
+                    "{} with {s:?}",
+                    cond.syntax()
+                );
+            }
+        }
+        let cond = HtnCondition::EqualsInt {
+            field: "happy".to_string(),
+            value: 1,
+            notted: false,
+            syntax: "happy == 1".to_string(),
+        };
+        assert!(cond.compile(&state).is_none());
+    }
+    #[test]
+    fn test_reflected_comparisons() {
+        let atr = AppTypeRegistry::default();
+        {
+            let mut atr = atr.write();
+            atr.register::<State>();
+            atr.register::<Location>();
+        }
+        let state = State {
+            e1: 1,
+            e2: 2,
+            floatyness: 2.0,
+            ..default()
+        };
+
+        // `<` and `<=` on floats used to be evaluated as `>` and `>=`
+        let less_than = |threshold: f32, orequals: bool| HtnCondition::LessThanFloat {
+            field: "floatyness".to_string(),
+            threshold,
+            orequals,
+            syntax: format!(
+                "floatyness {} {threshold}",
+                if orequals { "<=" } else { "<" }
+            ),
+        };
+        assert!(less_than(4.0, false).evaluate(&state, &atr));
+        assert!(!less_than(1.0, false).evaluate(&state, &atr));
+        assert!(!less_than(2.0, false).evaluate(&state, &atr));
+        assert!(less_than(2.0, true).evaluate(&state, &atr));
+        assert!(!less_than(1.0, true).evaluate(&state, &atr));
+
+        // `!=` between fields used to be evaluated as `==`
+        let not_equal = HtnCondition::EqualsIdentifier {
+            field: "e1".to_string(),
+            other_field: "e2".to_string(),
+            notted: true,
+            syntax: "e1 != e2".to_string(),
+        };
+        assert!(not_equal.evaluate(&state, &atr));
+        assert!(!not_equal.evaluate(
+            &State {
+                e2: 1,
+                ..state.clone()
+            },
+            &atr
+        ));
+
+        // comparing fields of different types used to compare the first field's type with itself,
+        // and panic comparing the values
+        let mismatched = HtnCondition::GreaterThanIdentifier {
+            field: "e1".to_string(),
+            other_field: "floatyness".to_string(),
+            orequals: false,
+            syntax: "e1 > floatyness".to_string(),
+        };
+        assert!(!mismatched.evaluate(&state, &atr));
+        assert!(mismatched.verify_types(&state, &atr).is_err());
+
+        // only numbers can be ordered, other fields are rejected rather than panicking
+        let unordered = HtnCondition::LessThanIdentifier {
+            field: "location".to_string(),
+            other_field: "location".to_string(),
+            orequals: true,
+            syntax: "location <= location".to_string(),
+        };
+        assert!(!unordered.evaluate(&state, &atr));
+        assert!(matches!(
+            unordered.verify_types(&state, &atr),
+            Err(HtnErr::Condition { .. })
+        ));
     }
 }
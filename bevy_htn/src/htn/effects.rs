@@ -1,4 +1,4 @@
-use crate::HtnStateTrait;
+use crate::{claims::ClaimKey, HtnStateTrait};
 
 use super::*;
 use crate::error::HtnErr;
@@ -6,6 +6,7 @@ use bevy::{
     prelude::*,
     reflect::{DynamicEnum, DynamicVariant, VariantInfo},
 };
+use std::{any::TypeId, sync::Arc};
 // use float_eq::*;
 
 // #[derive_float_eq(
@@ -66,6 +67,12 @@ pub enum Effect {
         field: String,
         syntax: String,
     },
+    /// Claims the value of the field for the supervisor while the plan runs, see `HtnClaims`.
+    /// Doesn't change the state.
+    Claim {
+        field: String,
+        syntax: String,
+    },
 }
 
 impl Effect {
@@ -80,6 +87,30 @@ impl Effect {
             Effect::SetNone { syntax, .. } => syntax,
             Effect::SetFloat { syntax, .. } => syntax,
             Effect::IncrementFloat { syntax, .. } => syntax,
+            Effect::Claim { syntax, .. } => syntax,
+        }
+    }
+    /// The name of the state field this effect modifies.
+    pub fn field(&self) -> &str {
+        match self {
+            Effect::SetBool { field, .. } => field,
+            Effect::SetInt { field, .. } => field,
+            Effect::SetIdentifier { field, .. } => field,
+            Effect::IncrementInt { field, .. } => field,
+            Effect::IncrementIdentifier { field, .. } => field,
+            Effect::SetEnum { field, .. } => field,
+            Effect::SetNone { field, .. } => field,
+            Effect::SetFloat { field, .. } => field,
+            Effect::IncrementFloat { field, .. } => field,
+            Effect::Claim { field, .. } => field,
+        }
+    }
+    /// The name of the field this effect copies or adds to the field it modifies, if any.
+    pub fn source_field(&self) -> Option<&str> {
+        match self {
+            Effect::SetIdentifier { field_source, .. }
+            | Effect::IncrementIdentifier { field_source, .. } => Some(field_source),
+            _ => None,
         }
     }
     pub fn verify_types<T: HtnStateTrait>(
@@ -102,7 +133,7 @@ impl Effect {
             })?;
         match self {
             Effect::SetBool { field, syntax, .. } => {
-                if reflected.field(field).is_none() {
+                if state_field(reflected, field).is_none() {
                     return Err(HtnErr::Bool {
                         syntax: syntax.clone(),
                         details: format!("Unknown state field `{field}` for {effect_noun}"),
@@ -110,7 +141,7 @@ impl Effect {
                 };
             }
             Effect::SetInt { field, syntax, .. } | Effect::IncrementInt { field, syntax, .. } => {
-                if reflected.field(field).is_none() {
+                if state_field(reflected, field).is_none() {
                     return Err(HtnErr::Int {
                         syntax: syntax.clone(),
                         details: format!("Unknown state field `{field}` for {effect_noun}"),
@@ -119,7 +150,7 @@ impl Effect {
             }
             Effect::SetFloat { field, syntax, .. }
             | Effect::IncrementFloat { field, syntax, .. } => {
-                if reflected.field(field).is_none() {
+                if state_field(reflected, field).is_none() {
                     return Err(HtnErr::Float {
                         syntax: syntax.clone(),
                         details: format!("Unknown state field `{field}` for {effect_noun}"),
@@ -133,13 +164,13 @@ impl Effect {
                 syntax,
                 ..
             } => {
-                let Some(field_val) = reflected.field(field) else {
+                let Some(field_val) = state_field(reflected, field) else {
                     return Err(HtnErr::Condition {
                         syntax: syntax.clone(),
                         details: format!("Unknown state field `{field}` for {effect_noun}"),
                     });
                 };
-                let Some(field_src_val) = reflected.field(field_source) else {
+                let Some(field_src_val) = state_field(reflected, field_source) else {
                     return Err(HtnErr::Condition {
                         syntax: syntax.clone(),
                         details: format!("Unknown state field `{field_source}` for {effect_noun}"),
@@ -165,13 +196,13 @@ impl Effect {
                 syntax,
                 ..
             } => {
-                let Some(field_val) = reflected.field(field) else {
+                let Some(field_val) = state_field(reflected, field) else {
                     return Err(HtnErr::Condition {
                         syntax: syntax.clone(),
                         details: format!("Unknown state field `{field}` for {effect_noun}"),
                     });
                 };
-                let Some(field_src_val) = reflected.field(field_source) else {
+                let Some(field_src_val) = state_field(reflected, field_source) else {
                     return Err(HtnErr::Condition {
                         syntax: syntax.clone(),
                         details: format!("Unknown state field `{field_source}` for {effect_noun}"),
@@ -191,7 +222,7 @@ impl Effect {
                 }
             }
             Effect::SetNone { field, syntax, .. } => {
-                let Some(val) = reflected.field(field) else {
+                let Some(val) = state_field(reflected, field) else {
                     return Err(HtnErr::Enum {
                         syntax: syntax.clone(),
                         details: format!("Unknown state field `{field}` for {effect_noun}"),
@@ -223,7 +254,7 @@ impl Effect {
                 syntax,
                 ..
             } => {
-                let Some(val) = reflected.field(field) else {
+                let Some(val) = state_field(reflected, field) else {
                     return Err(HtnErr::Enum {
                         syntax: syntax.clone(),
                         details: format!("Unknown state field `{field}` for {effect_noun}"),
@@ -263,6 +294,22 @@ impl Effect {
                     });
                 }
             }
+            Effect::Claim { field, syntax } => {
+                let Some(val) = state_field(reflected, field) else {
+                    return Err(HtnErr::Effect {
+                        syntax: syntax.clone(),
+                        details: format!("Unknown state field `{field}` for {effect_noun}"),
+                    });
+                };
+                if !ClaimKey::is_claimable(val) {
+                    return Err(HtnErr::Effect {
+                        syntax: syntax.clone(),
+                        details: format!(
+                            "State field `{field}` can't be claimed, its value can't be hashed"
+                        ),
+                    });
+                }
+            }
         }
         Ok(())
     }
@@ -275,7 +322,7 @@ impl Effect {
             .expect("State is not a struct");
         match self {
             Effect::SetBool { field, value, .. } => {
-                if let Some(val) = reflected.field_mut(field) {
+                if let Some(val) = state_field_mut(reflected, field) {
                     if let Some(b) = val.try_downcast_mut::<bool>() {
                         *b = *value;
                     }
@@ -284,7 +331,7 @@ impl Effect {
                 }
             }
             Effect::SetInt { field, value, .. } => {
-                if let Some(val) = reflected.field_mut(field) {
+                if let Some(val) = state_field_mut(reflected, field) {
                     if let Some(i) = val.try_downcast_mut::<i32>() {
                         *i = *value;
                     }
@@ -293,7 +340,7 @@ impl Effect {
                 }
             }
             Effect::SetFloat { field, value, .. } => {
-                if let Some(val) = reflected.field_mut(field) {
+                if let Some(val) = state_field_mut(reflected, field) {
                     if let Some(f) = val.try_downcast_mut::<f32>() {
                         *f = *value;
                     }
@@ -302,7 +349,7 @@ impl Effect {
                 }
             }
             Effect::IncrementInt { field, by, .. } => {
-                if let Some(val) = reflected.field_mut(field) {
+                if let Some(val) = state_field_mut(reflected, field) {
                     if let Some(i) = val.try_downcast_mut::<i32>() {
                         *i += *by;
                     }
@@ -311,7 +358,7 @@ impl Effect {
                 }
             }
             Effect::IncrementFloat { field, by, .. } => {
-                if let Some(val) = reflected.field_mut(field) {
+                if let Some(val) = state_field_mut(reflected, field) {
                     if let Some(f) = val.try_downcast_mut::<f32>() {
                         *f += *by;
                     }
@@ -325,11 +372,11 @@ impl Effect {
                 decrement,
                 ..
             } => {
-                let Some(newval) = reflected.field(field_source) else {
+                let Some(newval) = state_field(reflected, field_source) else {
                     panic!("Field {field_source} does not exist in the state");
                 };
                 let newval = newval.clone_value();
-                let Some(val) = reflected.field_mut(field) else {
+                let Some(val) = state_field_mut(reflected, field) else {
                     panic!("Field {field} does not exist in the state");
                 };
                 fn try_inc<T: std::ops::AddAssign + std::ops::SubAssign + Copy + 'static>(
@@ -372,15 +419,15 @@ impl Effect {
                 field_source,
                 ..
             } => {
-                let Some(newval) = reflected.field(field_source) else {
+                let Some(newval) = state_field(reflected, field_source) else {
                     panic!("Field {field_source} does not exist in the state");
                 };
                 let newval = newval.clone_value();
-                let val = reflected.field_mut(field).unwrap();
+                let val = state_field_mut(reflected, field).unwrap();
                 val.apply(newval.as_ref());
             }
             Effect::SetNone { field, .. } => {
-                let val = reflected.field_mut(field).unwrap();
+                let val = state_field_mut(reflected, field).unwrap();
                 let enum_variant = "None";
                 let state_dyn_enum = val.reflect_mut().as_enum().expect("Field is not an enum");
                 let enum_info = state_dyn_enum
@@ -401,7 +448,7 @@ impl Effect {
                 enum_variant,
                 ..
             } => {
-                if let Some(val) = reflected.field_mut(field) {
+                if let Some(val) = state_field_mut(reflected, field) {
                     let state_dyn_enum = val.reflect_mut().as_enum().expect("Field is not an enum");
                     let enum_info = state_dyn_enum
                         .get_represented_enum_info()
@@ -423,6 +470,325 @@ impl Effect {
                     panic!("Field {field} does not exist in the state");
                 }
             }
+            // claims are registered by the executor when the plan starts running
+            Effect::Claim { .. } => {}
+        }
+    }
+
+    /// Resolves this effect against the state type, returning None if the fields it uses don't
+    /// exist or have unexpected types (which `verify_types` reports as errors).
+    pub fn compile<T: HtnStateTrait>(&self, state: &T) -> Option<CompiledEffect> {
+        let reflected = state.reflect_ref().as_struct().ok()?;
+        let compiled = match self {
+            Effect::SetBool { field, value, .. } => CompiledEffect::SetBool {
+                field: FieldPath::resolve_typed::<bool>(reflected, field)?,
+                value: *value,
+            },
+            Effect::SetInt { field, value, .. } => CompiledEffect::SetInt {
+                field: FieldPath::resolve_typed::<i32>(reflected, field)?,
+                value: *value,
+            },
+            Effect::SetFloat { field, value, .. } => CompiledEffect::SetFloat {
+                field: FieldPath::resolve_typed::<f32>(reflected, field)?,
+                value: *value,
+            },
+            Effect::IncrementInt { field, by, .. } => CompiledEffect::IncrementInt {
+                field: FieldPath::resolve_typed::<i32>(reflected, field)?,
+                by: *by,
+            },
+            Effect::IncrementFloat { field, by, .. } => CompiledEffect::IncrementFloat {
+                field: FieldPath::resolve_typed::<f32>(reflected, field)?,
+                by: *by,
+            },
+            Effect::SetIdentifier {
+                field,
+                field_source,
+                ..
+            } => {
+                let path = FieldPath::resolve(reflected, field)?;
+                let source_path = FieldPath::resolve(reflected, field_source)?;
+                let type_id = represented_type_id(path.get(reflected)?)?;
+                if type_id != represented_type_id(source_path.get(reflected)?)? {
+                    return None;
+                }
+                CompiledEffect::CopyField {
+                    field: path,
+                    field_source: source_path,
+                    copy: copy_field_fn(type_id),
+                }
+            }
+            Effect::IncrementIdentifier {
+                field,
+                field_source,
+                decrement,
+                ..
+            } => {
+                let path = FieldPath::resolve(reflected, field)?;
+                let source_path = FieldPath::resolve(reflected, field_source)?;
+                let type_id = represented_type_id(path.get(reflected)?)?;
+                if type_id != represented_type_id(source_path.get(reflected)?)? {
+                    return None;
+                }
+                CompiledEffect::IncrementField {
+                    field: path,
+                    field_source: source_path,
+                    decrement: *decrement,
+                    increment: increment_field_fn(type_id)?,
+                }
+            }
+            Effect::SetNone { field, .. } => {
+                let path = FieldPath::resolve(reflected, field)?;
+                let value = unit_variant(path.get(reflected)?, None, "None")?;
+                CompiledEffect::SetVariant { field: path, value }
+            }
+            Effect::SetEnum {
+                field,
+                enum_type,
+                enum_variant,
+                ..
+            } => {
+                let path = FieldPath::resolve(reflected, field)?;
+                let value = unit_variant(path.get(reflected)?, Some(enum_type), enum_variant)?;
+                CompiledEffect::SetVariant { field: path, value }
+            }
+            Effect::Claim { field, .. } => CompiledEffect::Claim {
+                field: FieldPath::resolve(reflected, field)?,
+            },
+        };
+        Some(compiled)
+    }
+}
+
+/// Builds a unit variant of the enum type of `field`, checking the type name if given.
+fn unit_variant(
+    field: &dyn PartialReflect,
+    enum_type: Option<&str>,
+    enum_variant: &str,
+) -> Option<Arc<dyn PartialReflect>> {
+    let enum_info = field
+        .reflect_ref()
+        .as_enum()
+        .ok()?
+        .get_represented_enum_info()?;
+    if enum_type.is_some() && enum_info.type_path_table().ident() != enum_type {
+        return None;
+    }
+    let VariantInfo::Unit(_) = enum_info.variant(enum_variant)? else {
+        return None;
+    };
+    let mut value = DynamicEnum::new(enum_variant, DynamicVariant::Unit);
+    value.set_represented_type(field.get_represented_type_info());
+    Some(Arc::new(value))
+}
+
+/// Sets field `field` to the value of field `field_source` in a state struct.
+pub type CopyFieldFn = fn(&mut dyn Struct, &FieldPath, &FieldPath);
+
+/// Adds (or subtracts, if the bool is true) field `field_source` to field `field`.
+pub type IncrementFieldFn = fn(&mut dyn Struct, &FieldPath, &FieldPath, bool);
+
+fn copy_field<N: Copy + 'static>(
+    state: &mut dyn Struct,
+    field: &FieldPath,
+    field_source: &FieldPath,
+) {
+    let Some(value) = field_source
+        .get(state)
+        .and_then(|val| val.try_downcast_ref::<N>())
+        .copied()
+    else {
+        return;
+    };
+    if let Some(val) = field
+        .get_mut(state)
+        .and_then(|val| val.try_downcast_mut::<N>())
+    {
+        *val = value;
+    }
+}
+
+fn copy_field_reflect(state: &mut dyn Struct, field: &FieldPath, field_source: &FieldPath) {
+    let Some(value) = field_source.get(state).map(|val| val.clone_value()) else {
+        return;
+    };
+    if let Some(val) = field.get_mut(state) {
+        val.apply(value.as_ref());
+    }
+}
+
+fn copy_field_fn(type_id: TypeId) -> CopyFieldFn {
+    if type_id == TypeId::of::<bool>() {
+        copy_field::<bool>
+    } else if type_id == TypeId::of::<i32>() {
+        copy_field::<i32>
+    } else if type_id == TypeId::of::<f32>() {
+        copy_field::<f32>
+    } else {
+        copy_field_reflect
+    }
+}
+
+fn increment_field<N: std::ops::AddAssign + std::ops::SubAssign + Copy + 'static>(
+    state: &mut dyn Struct,
+    field: &FieldPath,
+    field_source: &FieldPath,
+    decrement: bool,
+) {
+    let Some(by) = field_source
+        .get(state)
+        .and_then(|val| val.try_downcast_ref::<N>())
+        .copied()
+    else {
+        return;
+    };
+    if let Some(val) = field
+        .get_mut(state)
+        .and_then(|val| val.try_downcast_mut::<N>())
+    {
+        if decrement {
+            *val -= by;
+        } else {
+            *val += by;
+        }
+    }
+}
+
+fn increment_field_fn(type_id: TypeId) -> Option<IncrementFieldFn> {
+    let increments: [(TypeId, IncrementFieldFn); 13] = [
+        (TypeId::of::<i32>(), increment_field::<i32>),
+        (TypeId::of::<u32>(), increment_field::<u32>),
+        (TypeId::of::<usize>(), increment_field::<usize>),
+        (TypeId::of::<i8>(), increment_field::<i8>),
+        (TypeId::of::<i16>(), increment_field::<i16>),
+        (TypeId::of::<i64>(), increment_field::<i64>),
+        (TypeId::of::<i128>(), increment_field::<i128>),
+        (TypeId::of::<f32>(), increment_field::<f32>),
+        (TypeId::of::<f64>(), increment_field::<f64>),
+        (TypeId::of::<u8>(), increment_field::<u8>),
+        (TypeId::of::<u16>(), increment_field::<u16>),
+        (TypeId::of::<u64>(), increment_field::<u64>),
+        (TypeId::of::<u128>(), increment_field::<u128>),
+    ];
+    increments
+        .into_iter()
+        .find(|(id, _)| *id == type_id)
+        .map(|(_, increment)| increment)
+}
+
+/// An effect resolved against the state type by [`Effect::compile`].
+///
+/// Fields are referred to by their indices in the state struct, so applying doesn't need to look
+/// them up by name or consult the type registry.
+#[derive(Clone, Debug)]
+pub enum CompiledEffect {
+    SetBool {
+        field: FieldPath,
+        value: bool,
+    },
+    SetInt {
+        field: FieldPath,
+        value: i32,
+    },
+    SetFloat {
+        field: FieldPath,
+        value: f32,
+    },
+    IncrementInt {
+        field: FieldPath,
+        by: i32,
+    },
+    IncrementFloat {
+        field: FieldPath,
+        by: f32,
+    },
+    /// Sets an enum field to a unit variant, or an Option to None.
+    SetVariant {
+        field: FieldPath,
+        value: Arc<dyn PartialReflect>,
+    },
+    CopyField {
+        field: FieldPath,
+        field_source: FieldPath,
+        copy: CopyFieldFn,
+    },
+    IncrementField {
+        field: FieldPath,
+        field_source: FieldPath,
+        decrement: bool,
+        increment: IncrementFieldFn,
+    },
+    /// Doesn't change the state, see [`Effect::Claim`].
+    Claim {
+        field: FieldPath,
+    },
+}
+
+impl CompiledEffect {
+    /// The path of the state field this effect modifies.
+    pub fn field(&self) -> &FieldPath {
+        match self {
+            CompiledEffect::SetBool { field, .. } => field,
+            CompiledEffect::SetInt { field, .. } => field,
+            CompiledEffect::SetFloat { field, .. } => field,
+            CompiledEffect::IncrementInt { field, .. } => field,
+            CompiledEffect::IncrementFloat { field, .. } => field,
+            CompiledEffect::SetVariant { field, .. } => field,
+            CompiledEffect::CopyField { field, .. } => field,
+            CompiledEffect::IncrementField { field, .. } => field,
+            CompiledEffect::Claim { field } => field,
+        }
+    }
+
+    pub fn apply(&self, state: &mut dyn Struct) {
+        fn typed<'a, N: 'static>(
+            state: &'a mut dyn Struct,
+            field: &FieldPath,
+        ) -> Option<&'a mut N> {
+            field.get_mut(state)?.try_downcast_mut::<N>()
+        }
+        match self {
+            CompiledEffect::SetBool { field, value } => {
+                if let Some(b) = typed::<bool>(state, field) {
+                    *b = *value;
+                }
+            }
+            CompiledEffect::SetInt { field, value } => {
+                if let Some(i) = typed::<i32>(state, field) {
+                    *i = *value;
+                }
+            }
+            CompiledEffect::SetFloat { field, value } => {
+                if let Some(f) = typed::<f32>(state, field) {
+                    *f = *value;
+                }
+            }
+            CompiledEffect::IncrementInt { field, by } => {
+                if let Some(i) = typed::<i32>(state, field) {
+                    *i += *by;
+                }
+            }
+            CompiledEffect::IncrementFloat { field, by } => {
+                if let Some(f) = typed::<f32>(state, field) {
+                    *f += *by;
+                }
+            }
+            CompiledEffect::SetVariant { field, value } => {
+                if let Some(val) = field.get_mut(state) {
+                    val.apply(value.as_ref());
+                }
+            }
+            CompiledEffect::CopyField {
+                field,
+                field_source,
+                copy,
+            } => copy(state, field, field_source),
+            CompiledEffect::IncrementField {
+                field,
+                field_source,
+                decrement,
+                increment,
+            } => increment(state, field, field_source, *decrement),
+            CompiledEffect::Claim { .. } => {}
         }
     }
 }
@@ -655,5 +1021,53 @@ mod tests {
 
         // there is no SetSome yet. can maybe do it be constructing the default value of the Option Some,
         // but that should probably be restricted to expected_effects. bit unpleasant.
+
+        // compiled effects must change the state the same way as applying them via reflection.
+        let src = r#"
+            schema {
+                version: 0.1.0
+            }
+            primitive_task "Compiled Effects Test" {
+                operator: DummyOperator
+                effects: [
+                    happy = true,
+                    energy = 100,
+                    floatyness = 4.0,
+                    energy += 10,
+                    energy -= 10,
+                    e1 = e2,
+                    energy += e1,
+                    energy -= e1,
+                    location = Location::Park,
+                    opt = None,
+                    opt2 = opt,
+                ]
+            }
+            "#;
+        let htn = parse_htn::<State>(src).expect("Failed to parse htn");
+        let Some(Task::Primitive(pt)) = &htn.tasks.first() else {
+            panic!("Task should exist");
+        };
+        assert_eq!(pt.effects.len(), 11);
+        for effect in pt.effects.iter() {
+            let compiled = effect
+                .compile(&initial_state)
+                .unwrap_or_else(|| panic!("Effect should compile: {}", effect.syntax()));
+            let mut expected = initial_state.clone();
+            effect.apply(&mut expected, &atr);
+            let mut state = initial_state.clone();
+            compiled.apply(state.reflect_mut().as_struct().unwrap());
+            assert!(
+                state.reflect_partial_eq(&expected).unwrap(),
+                "{}: {state:?} != {expected:?}",
+                effect.syntax()
+            );
+        }
+        let effect = Effect::SetBool {
+            field: "energy".to_string(),
+            value: true,
+            syntax: "energy = true".to_string(),
+        };
+        assert!(effect.compile(&initial_state).is_none());
     }
 }
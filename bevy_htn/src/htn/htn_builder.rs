@@ -1,18 +1,31 @@
 use crate::{error::HtnErr, HtnStateTrait};
 
 use super::*;
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
 
 #[derive(Debug, Reflect, Clone, Default)]
 pub struct HtnSchema {
     pub version: String,
 }
 
+/// Index of a task in an [`HTN`], resolved from the task name when the HTN is built.
+#[derive(Debug, Reflect, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TaskId(usize);
+
+impl TaskId {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
 /// This is the HTN domain - a list of all the compound and primitive tasks.
 #[derive(Debug, Reflect, Clone)]
 pub struct HTN<T: HtnStateTrait> {
     pub tasks: Vec<Task<T>>,
     pub schema: HtnSchema,
+    task_ids: HashMap<String, TaskId>,
+    #[reflect(ignore)]
+    compiled: bool,
 }
 
 impl<T: HtnStateTrait> HTN<T> {
@@ -30,10 +43,17 @@ impl<T: HtnStateTrait> HTN<T> {
 
     /// Returns the task with the given name.
     pub fn get_task_by_name(&self, name: &str) -> Option<&Task<T>> {
-        self.tasks.iter().find(|task| match task {
-            Task::Primitive(primitive) => primitive.name == name,
-            Task::Compound(compound) => compound.name == name,
-        })
+        self.task_id(name).and_then(|id| self.get_task(id))
+    }
+
+    /// Returns the task with the given id.
+    pub fn get_task(&self, id: TaskId) -> Option<&Task<T>> {
+        self.tasks.get(id.0)
+    }
+
+    /// Returns the id of the task with the given name.
+    pub fn task_id(&self, name: &str) -> Option<TaskId> {
+        self.task_ids.get(name).copied()
     }
 
     /// Returns the first (compound) task in the HTN.
@@ -41,13 +61,20 @@ impl<T: HtnStateTrait> HTN<T> {
         self.tasks.first().expect("No root task found")
     }
 
+    /// Returns the id of the first (compound) task in the HTN.
+    pub fn root_task_id(&self) -> TaskId {
+        TaskId(0)
+    }
+
     /// Verifies that every rust type used in the HTN is registered in the type registry, to
     /// avoid any runtime errors executing the HTN.
     ///
     /// Call this after parsing the HTN before trying to use it.
     pub fn verify_all(&self, state: &T, atr: &AppTypeRegistry) -> Result<(), HtnErr> {
+        self.verify_subtasks()?;
         self.verify_conditions(state, atr)?;
         self.verify_effects(state, atr)?;
+        self.verify_durations(state)?;
         self.verify_operators(state, atr)?;
         Ok(())
     }
@@ -56,8 +83,10 @@ impl<T: HtnStateTrait> HTN<T> {
     /// Doesn't check that operators are registered.
     /// Used in tests that check the planner output without actually running the HTNs.
     pub fn verify_without_operators(&self, state: &T, atr: &AppTypeRegistry) -> Result<(), HtnErr> {
+        self.verify_subtasks()?;
         self.verify_conditions(state, atr)?;
         self.verify_effects(state, atr)?;
+        self.verify_durations(state)?;
         Ok(())
     }
 
@@ -73,6 +102,67 @@ impl<T: HtnStateTrait> HTN<T> {
         Ok(())
     }
 
+    /// Resolves every condition and effect to a compiled form that accesses state fields by index,
+    /// instead of looking them up by name through reflection each time they're used.
+    ///
+    /// [`HTNBuilder::build`] compiles against the default state, so this only needs calling again
+    /// if the tasks are changed. Tasks with conditions or effects that can't be resolved keep
+    /// using reflection, `verify_all` reports why.
+    pub fn compile(&mut self, state: &T) {
+        for task in self.tasks.iter_mut() {
+            if !task.compile(state) {
+                warn!(
+                    "Task `{}` could not be compiled, it will be evaluated using reflection",
+                    task.name()
+                );
+            }
+        }
+        self.compiled = true;
+    }
+
+    /// True if `compile` has been called.
+    pub fn is_compiled(&self) -> bool {
+        self.compiled
+    }
+
+    /// Drops the compiled forms, so everything is evaluated using reflection.
+    #[cfg(test)]
+    pub(crate) fn decompile(&mut self) {
+        for task in self.tasks.iter_mut() {
+            match task {
+                Task::Primitive(primitive) => primitive.decompile(),
+                Task::Compound(compound) => compound
+                    .methods
+                    .iter_mut()
+                    .for_each(|method| method.decompile()),
+            }
+        }
+        self.compiled = false;
+    }
+
+    /// Verifies that every subtask used in a method is a task in this HTN.
+    pub fn verify_subtasks(&self) -> Result<(), HtnErr> {
+        for task in self.tasks.iter() {
+            let Task::Compound(compound) = task else {
+                continue;
+            };
+            for method in compound.methods.iter() {
+                let unknown = method
+                    .subtasks
+                    .iter()
+                    .zip(method.subtask_ids.iter())
+                    .find(|(_, id)| id.is_none());
+                if let Some((subtask, _)) = unknown {
+                    return Err(HtnErr::Subtask {
+                        task: compound.name.clone(),
+                        subtask: subtask.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn verify_effects(&self, state: &T, atr: &AppTypeRegistry) -> Result<(), HtnErr> {
         for task in self.tasks.iter() {
             debug!("Verifying effects for task: {}", task.name());
@@ -88,6 +178,16 @@ impl<T: HtnStateTrait> HTN<T> {
         }
         Ok(())
     }
+
+    /// Verifies that durations read from the state use numeric state fields.
+    pub fn verify_durations(&self, state: &T) -> Result<(), HtnErr> {
+        for task in self.tasks.iter() {
+            if let Task::Primitive(primitive) = task {
+                primitive.verify_duration(state)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 pub struct HTNBuilder<T: HtnStateTrait> {
@@ -123,14 +223,41 @@ impl<T: HtnStateTrait> HTNBuilder<T> {
         Ok(self)
     }
 
-    pub fn build(self) -> HTN<T> {
-        HTN {
+    /// Builds the HTN, assigning each task an id, resolving method subtasks to task ids, and
+    /// compiling conditions and effects against the default state, see [`HTN::compile`].
+    pub fn build(mut self) -> HTN<T> {
+        let task_ids = self
+            .tasks
+            .iter()
+            .enumerate()
+            .map(|(idx, task)| (task.name().to_string(), TaskId(idx)))
+            .collect::<HashMap<_, _>>();
+        for task in self.tasks.iter_mut() {
+            let Task::Compound(compound) = task else {
+                continue;
+            };
+            for method in compound.methods.iter_mut() {
+                method.subtask_ids = method
+                    .subtasks
+                    .iter()
+                    .map(|name| task_ids.get(name).copied())
+                    .collect();
+            }
+        }
+        let mut htn = HTN {
             tasks: self.tasks,
             schema: self.schema,
-        }
+            task_ids,
+            compiled: false,
+        };
+        htn.compile(&T::default());
+        htn
     }
 }
 
+// tasks are stored side by side and looked up by index while planning, boxing primitives would
+// cost an extra indirection for every lookup.
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug, Reflect)]
 pub enum Task<T: HtnStateTrait> {
     Primitive(PrimitiveTask<T>),
@@ -144,6 +271,12 @@ impl<T: HtnStateTrait> Task<T> {
             Task::Compound(compound) => &compound.name,
         }
     }
+    pub fn compile(&mut self, state: &T) -> bool {
+        match self {
+            Task::Primitive(primitive) => primitive.compile(state),
+            Task::Compound(compound) => compound.compile(state),
+        }
+    }
     pub fn verify_effects(&self, state: &T, atr: &AppTypeRegistry) -> Result<(), HtnErr> {
         match self {
             Task::Primitive(primitive) => primitive.verify_effects(state, atr),
@@ -11,6 +11,7 @@ pub use task_compound::*;
 pub use task_primitive::*;
 
 use bevy::{prelude::*, reflect::TypeRegistration};
+use std::{any::TypeId, sync::Arc};
 
 /// A wrapper around the TypeRegistry with some convenience methods.
 pub trait AppTypeRegistryExt {
@@ -26,3 +27,87 @@ impl AppTypeRegistryExt for AppTypeRegistry {
             .cloned()
     }
 }
+
+/// Returns the index of the named field in a reflected struct.
+pub(crate) fn field_index(state: &dyn Struct, name: &str) -> Option<usize> {
+    (0..state.field_len()).find(|idx| state.name_at(*idx) == Some(name))
+}
+
+/// Returns the named field of a reflected state. Names can be paths to fields of nested structs,
+/// eg. `world.coins`.
+pub(crate) fn state_field<'a>(state: &'a dyn Struct, name: &str) -> Option<&'a dyn PartialReflect> {
+    match name.split_once('.') {
+        None => state.field(name),
+        Some((head, rest)) => state_field(state.field(head)?.reflect_ref().as_struct().ok()?, rest),
+    }
+}
+
+/// Mutable version of [`state_field`].
+pub(crate) fn state_field_mut<'a>(
+    state: &'a mut dyn Struct,
+    name: &str,
+) -> Option<&'a mut dyn PartialReflect> {
+    match name.split_once('.') {
+        None => state.field_mut(name),
+        Some((head, rest)) => {
+            state_field_mut(state.field_mut(head)?.reflect_mut().as_struct().ok()?, rest)
+        }
+    }
+}
+
+/// The indices of a state field, and of the nested structs leading to it for paths like
+/// `world.coins`, so compiled conditions and effects can access it without looking up names.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldPath(Arc<[usize]>);
+
+impl FieldPath {
+    /// Resolves a field name, which can be a path to a field of a nested struct.
+    pub(crate) fn resolve(state: &dyn Struct, name: &str) -> Option<Self> {
+        let mut indices = Vec::new();
+        let mut current = state;
+        let mut parts = name.split('.').peekable();
+        while let Some(part) = parts.next() {
+            let idx = field_index(current, part)?;
+            indices.push(idx);
+            if parts.peek().is_some() {
+                current = current.field_at(idx)?.reflect_ref().as_struct().ok()?;
+            }
+        }
+        Some(Self(indices.into()))
+    }
+
+    /// Resolves a field name, if the field is of type `F`.
+    pub(crate) fn resolve_typed<F: 'static>(state: &dyn Struct, name: &str) -> Option<Self> {
+        let path = Self::resolve(state, name)?;
+        path.get(state)?.try_downcast_ref::<F>()?;
+        Some(path)
+    }
+
+    /// The index of the field in each struct along the path, starting with the state.
+    pub fn indices(&self) -> &[usize] {
+        &self.0
+    }
+
+    pub fn get<'a>(&self, state: &'a dyn Struct) -> Option<&'a dyn PartialReflect> {
+        let (last, parents) = self.0.split_last()?;
+        let mut current = state;
+        for idx in parents {
+            current = current.field_at(*idx)?.reflect_ref().as_struct().ok()?;
+        }
+        current.field_at(*last)
+    }
+
+    pub fn get_mut<'a>(&self, state: &'a mut dyn Struct) -> Option<&'a mut dyn PartialReflect> {
+        let (last, parents) = self.0.split_last()?;
+        let mut current = state;
+        for idx in parents {
+            current = current.field_at_mut(*idx)?.reflect_mut().as_struct().ok()?;
+        }
+        current.field_at_mut(*last)
+    }
+}
+
+/// Returns the represented type of a reflected value.
+pub(crate) fn represented_type_id(value: &dyn PartialReflect) -> Option<TypeId> {
+    value.get_represented_type_info().map(|info| info.type_id())
+}
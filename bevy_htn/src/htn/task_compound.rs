@@ -8,10 +8,70 @@ use std::marker::PhantomData;
 pub struct Method<T: Reflect> {
     pub name: Option<String>,
     pub preconditions: Vec<HtnCondition>,
-    pub subtasks: Vec<String>, // Just the task names now
+    pub subtasks: Vec<String>,
+    /// Ids of `subtasks`, resolved when the HTN is built. None if there's no task by that name.
+    pub subtask_ids: Vec<Option<TaskId>>,
+    #[reflect(ignore)]
+    compiled_preconditions: Option<Vec<CompiledCondition>>,
     _phantom: PhantomData<T>,
 }
 
+impl<T: HtnStateTrait> Method<T> {
+    /// Returns true if all preconditions are met.
+    pub fn preconditions_met(&self, state: &T, atr: &AppTypeRegistry) -> bool {
+        if let Some(compiled) = &self.compiled_preconditions {
+            let reflected = state
+                .reflect_ref()
+                .as_struct()
+                .expect("State is not a struct");
+            return compiled.iter().all(|cond| cond.evaluate(reflected));
+        }
+        self.preconditions
+            .iter()
+            .all(|cond| cond.evaluate(state, atr))
+    }
+
+    pub fn find_first_failing_precondition(
+        &self,
+        state: &T,
+        atr: &AppTypeRegistry,
+    ) -> Option<&HtnCondition> {
+        if let Some(compiled) = &self.compiled_preconditions {
+            let reflected = state
+                .reflect_ref()
+                .as_struct()
+                .expect("State is not a struct");
+            return compiled
+                .iter()
+                .position(|cond| !cond.evaluate(reflected))
+                .map(|idx| &self.preconditions[idx]);
+        }
+        self.preconditions
+            .iter()
+            .find(|cond| !cond.evaluate(state, atr))
+    }
+
+    /// Resolves preconditions to their compiled forms, returning false (and leaving the method
+    /// using reflection) if any of them can't be resolved.
+    pub fn compile(&mut self, state: &T) -> bool {
+        self.compiled_preconditions = self
+            .preconditions
+            .iter()
+            .map(|c| c.compile(state))
+            .collect();
+        self.compiled_preconditions.is_some()
+    }
+
+    pub fn is_compiled(&self) -> bool {
+        self.compiled_preconditions.is_some()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn decompile(&mut self) {
+        self.compiled_preconditions = None;
+    }
+}
+
 #[derive(Clone, Debug, Reflect)]
 pub struct CompoundTask<T: HtnStateTrait> {
     pub name: String,
@@ -31,12 +91,7 @@ impl<T: HtnStateTrait> CompoundTask<T> {
             .iter()
             .enumerate()
             .skip(skip)
-            .find(|(_, method)| {
-                method
-                    .preconditions
-                    .iter()
-                    .all(|cond| cond.evaluate(state, atr))
-            })
+            .find(|(_, method)| method.preconditions_met(state, atr))
             .map(|(i, method)| (method, i))
     }
     pub fn verify_conditions(&self, state: &T, atr: &AppTypeRegistry) -> Result<(), HtnErr> {
@@ -47,6 +102,14 @@ impl<T: HtnStateTrait> CompoundTask<T> {
         }
         Ok(())
     }
+    /// Compiles every method, returning false if any of them can't be compiled.
+    pub fn compile(&mut self, state: &T) -> bool {
+        let mut all_compiled = true;
+        for method in self.methods.iter_mut() {
+            all_compiled &= method.compile(state);
+        }
+        all_compiled
+    }
 }
 
 pub struct CompoundTaskBuilder<T: HtnStateTrait> {
@@ -116,6 +179,8 @@ impl<T: Reflect> MethodBuilder<T> {
         Method {
             preconditions: self.preconditions,
             subtasks: self.subtasks,
+            subtask_ids: Vec::new(),
+            compiled_preconditions: None,
             name: self.name,
             _phantom: PhantomData,
         }
@@ -32,6 +32,50 @@ impl Operator {
     }
 }
 
+/// How long a primitive task is expected to take to execute, in seconds.
+#[derive(Clone, Debug, Reflect, PartialEq)]
+pub enum TaskDuration {
+    /// `duration: 2.5`
+    Fixed(f32),
+    /// `duration: some_field`, read from a numeric state field when the task is planned.
+    Field(String),
+}
+
+impl TaskDuration {
+    /// The estimated duration, given the state the task will be executed in.
+    pub fn estimate<T: HtnStateTrait>(&self, state: &T) -> Option<f32> {
+        match self {
+            TaskDuration::Fixed(secs) => Some(*secs),
+            TaskDuration::Field(field) => state
+                .reflect_ref()
+                .as_struct()
+                .ok()
+                .and_then(|s| state_field(s, field))
+                .and_then(reflect_as_f32),
+        }
+    }
+}
+
+/// Converts a reflected number to f32, if it's one of the numeric types we support.
+fn reflect_as_f32(value: &dyn PartialReflect) -> Option<f32> {
+    let value = value.try_as_reflect()?.as_any();
+    if let Some(v) = value.downcast_ref::<f32>() {
+        Some(*v)
+    } else if let Some(v) = value.downcast_ref::<f64>() {
+        Some(*v as f32)
+    } else if let Some(v) = value.downcast_ref::<i32>() {
+        Some(*v as f32)
+    } else if let Some(v) = value.downcast_ref::<u32>() {
+        Some(*v as f32)
+    } else if let Some(v) = value.downcast_ref::<i64>() {
+        Some(*v as f32)
+    } else if let Some(v) = value.downcast_ref::<u64>() {
+        Some(*v as f32)
+    } else {
+        value.downcast_ref::<usize>().map(|v| *v as f32)
+    }
+}
+
 #[derive(Clone, Debug, Reflect)]
 pub struct PrimitiveTask<T: HtnStateTrait> {
     pub name: String,
@@ -39,9 +83,27 @@ pub struct PrimitiveTask<T: HtnStateTrait> {
     pub preconditions: Vec<HtnCondition>,
     pub effects: Vec<Effect>,
     pub expected_effects: Vec<Effect>,
+    pub duration: Option<TaskDuration>,
+    /// `timeout: 10`, the task fails if it's still executing after this many seconds.
+    pub timeout: Option<f32>,
+    /// `retries: 2`, how many times the task is started again after failing, before the plan
+    /// fails.
+    pub retries: usize,
+    /// `retry_delay: 0.5`, seconds to wait after a failure before retrying.
+    pub retry_delay: f32,
+    #[reflect(ignore)]
+    compiled: Option<CompiledPrimitive>,
     _phantom: PhantomData<T>,
 }
 
+/// Compiled forms of a primitive task's preconditions and effects, in the same order.
+#[derive(Clone, Debug)]
+struct CompiledPrimitive {
+    preconditions: Vec<CompiledCondition>,
+    effects: Vec<CompiledEffect>,
+    expected_effects: Vec<CompiledEffect>,
+}
+
 impl<T: HtnStateTrait> PrimitiveTask<T> {
     /// To execute a primitive task is to either:
     /// - insert the operator component into an entity
@@ -109,6 +171,16 @@ impl<T: HtnStateTrait> PrimitiveTask<T> {
     }
 
     pub fn apply_effects(&self, state: &mut T, atr: &AppTypeRegistry) {
+        if let Some(compiled) = &self.compiled {
+            let reflected = state
+                .reflect_mut()
+                .as_struct()
+                .expect("State is not a struct");
+            for effect in compiled.effects.iter() {
+                effect.apply(reflected);
+            }
+            return;
+        }
         for effect in self.effects.iter() {
             // info!("APPLY: {effect:?}");
             effect.apply(state, atr);
@@ -116,12 +188,69 @@ impl<T: HtnStateTrait> PrimitiveTask<T> {
     }
 
     pub fn apply_expected_effects(&self, state: &mut T, atr: &AppTypeRegistry) {
+        if let Some(compiled) = &self.compiled {
+            let reflected = state
+                .reflect_mut()
+                .as_struct()
+                .expect("State is not a struct");
+            for effect in compiled.expected_effects.iter() {
+                effect.apply(reflected);
+            }
+            return;
+        }
         for effect in self.expected_effects.iter() {
             // info!("APPLY(expected): {effect:?}");
             effect.apply(state, atr);
         }
     }
 
+    /// The compiled effects followed by expected effects, if this task has been compiled.
+    pub fn compiled_effects(&self) -> Option<impl Iterator<Item = &CompiledEffect>> {
+        self.compiled
+            .as_ref()
+            .map(|c| c.effects.iter().chain(c.expected_effects.iter()))
+    }
+
+    /// Resolves preconditions and effects to their compiled forms, returning false (and leaving
+    /// the task using reflection) if any of them can't be resolved.
+    pub fn compile(&mut self, state: &T) -> bool {
+        let preconditions = self
+            .preconditions
+            .iter()
+            .map(|c| c.compile(state))
+            .collect::<Option<Vec<_>>>();
+        let effects = self
+            .effects
+            .iter()
+            .map(|e| e.compile(state))
+            .collect::<Option<Vec<_>>>();
+        let expected_effects = self
+            .expected_effects
+            .iter()
+            .map(|e| e.compile(state))
+            .collect::<Option<Vec<_>>>();
+        self.compiled = match (preconditions, effects, expected_effects) {
+            (Some(preconditions), Some(effects), Some(expected_effects)) => {
+                Some(CompiledPrimitive {
+                    preconditions,
+                    effects,
+                    expected_effects,
+                })
+            }
+            _ => None,
+        };
+        self.compiled.is_some()
+    }
+
+    pub fn is_compiled(&self) -> bool {
+        self.compiled.is_some()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn decompile(&mut self) {
+        self.compiled = None;
+    }
+
     /// Checks any field names used in effects, expected_effects, are present in the state.
     pub fn verify_effects(&self, state: &T, atr: &AppTypeRegistry) -> Result<(), HtnErr> {
         for effect in self.effects.iter() {
@@ -140,6 +269,25 @@ impl<T: HtnStateTrait> PrimitiveTask<T> {
         Ok(())
     }
 
+    /// The estimated time to execute this task in the given state, if it has a duration.
+    pub fn estimate_duration(&self, state: &T) -> Option<f32> {
+        self.duration.as_ref().and_then(|d| d.estimate(state))
+    }
+
+    /// Checks that a duration read from the state uses a numeric field.
+    pub fn verify_duration(&self, state: &T) -> Result<(), HtnErr> {
+        let Some(TaskDuration::Field(field)) = &self.duration else {
+            return Ok(());
+        };
+        if self.estimate_duration(state).is_none() {
+            return Err(HtnErr::Duration {
+                task: self.name.clone(),
+                details: format!("State does not have a numeric field `{field}`"),
+            });
+        }
+        Ok(())
+    }
+
     /// Checks that every operator has the correct type registry entries and that any fields used
     /// by operators are also present in the state.
     pub fn verify_operator(&self, state: &T, atr: &AppTypeRegistry) -> Result<(), HtnErr> {
@@ -191,16 +339,74 @@ impl<T: HtnStateTrait> PrimitiveTask<T> {
 
     /// Returns true if all preconditions are met.
     pub fn preconditions_met(&self, state: &T, atr: &AppTypeRegistry) -> bool {
+        if let Some(compiled) = &self.compiled {
+            let reflected = state
+                .reflect_ref()
+                .as_struct()
+                .expect("State is not a struct");
+            return compiled
+                .preconditions
+                .iter()
+                .all(|cond| cond.evaluate(reflected));
+        }
         self.preconditions
             .iter()
             .all(|cond| cond.evaluate(state, atr))
     }
 
+    /// Returns true if all preconditions are met, for a task that's already executing.
+    ///
+    /// A failing precondition is allowed if the state already holds the value this task's effects
+    /// or expected effects would give the field it tests, eg. a move task that has arrived.
+    pub fn preconditions_met_while_running(&self, state: &T, atr: &AppTypeRegistry) -> bool {
+        if self.preconditions_met(state, atr) {
+            return true;
+        }
+        let mut achieved = state.clone();
+        self.apply_effects(&mut achieved, atr);
+        self.apply_expected_effects(&mut achieved, atr);
+        let (Ok(reflected), Ok(achieved)) = (
+            state.reflect_ref().as_struct(),
+            achieved.reflect_ref().as_struct(),
+        ) else {
+            return false;
+        };
+        self.preconditions.iter().all(|cond| {
+            if cond.evaluate(state, atr) {
+                return true;
+            }
+            let writes_field = self
+                .effects
+                .iter()
+                .chain(self.expected_effects.iter())
+                .any(|e| e.field() == cond.field());
+            writes_field
+                && match (
+                    state_field(reflected, cond.field()),
+                    state_field(achieved, cond.field()),
+                ) {
+                    (Some(now), Some(after)) => now.reflect_partial_eq(after).unwrap_or(false),
+                    _ => false,
+                }
+        })
+    }
+
     pub fn find_first_failing_precondition(
         &self,
         state: &T,
         atr: &AppTypeRegistry,
     ) -> Option<&HtnCondition> {
+        if let Some(compiled) = &self.compiled {
+            let reflected = state
+                .reflect_ref()
+                .as_struct()
+                .expect("State is not a struct");
+            return compiled
+                .preconditions
+                .iter()
+                .position(|cond| !cond.evaluate(reflected))
+                .map(|idx| &self.preconditions[idx]);
+        }
         self.preconditions
             .iter()
             .find(|cond| !cond.evaluate(state, atr))
@@ -214,6 +420,10 @@ pub struct PrimitiveTaskBuilder<T: HtnStateTrait> {
     preconditions: Vec<HtnCondition>,
     effects: Vec<Effect>,
     expected_effects: Vec<Effect>,
+    duration: Option<TaskDuration>,
+    timeout: Option<f32>,
+    retries: usize,
+    retry_delay: f32,
     _phantom: PhantomData<T>,
 }
 
@@ -225,6 +435,10 @@ impl<T: HtnStateTrait> PrimitiveTaskBuilder<T> {
             preconditions: Vec::new(),
             effects: Vec::new(),
             expected_effects: Vec::new(),
+            duration: None,
+            timeout: None,
+            retries: 0,
+            retry_delay: 0.0,
             _phantom: PhantomData,
         }
     }
@@ -249,6 +463,26 @@ impl<T: HtnStateTrait> PrimitiveTaskBuilder<T> {
         self
     }
 
+    pub fn duration(mut self, duration: TaskDuration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    pub fn timeout(mut self, secs: f32) -> Self {
+        self.timeout = Some(secs);
+        self
+    }
+
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn retry_delay(mut self, secs: f32) -> Self {
+        self.retry_delay = secs;
+        self
+    }
+
     pub fn build(self) -> PrimitiveTask<T> {
         PrimitiveTask {
             name: self.name,
@@ -258,6 +492,11 @@ impl<T: HtnStateTrait> PrimitiveTaskBuilder<T> {
             preconditions: self.preconditions,
             effects: self.effects,
             expected_effects: self.expected_effects,
+            duration: self.duration,
+            timeout: self.timeout,
+            retries: self.retries,
+            retry_delay: self.retry_delay,
+            compiled: None,
             _phantom: PhantomData,
         }
     }
@@ -1,11 +1,11 @@
 use crate::dsl::parse_htn;
 use crate::htn::HTN;
-use crate::HtnStateTrait;
+use crate::{rng::HtnRng, HtnStateTrait};
 use bevy::asset::{io::Reader, AssetLoader, LoadContext};
 use bevy::prelude::*;
 use futures_lite::AsyncReadExt;
 use rand::Rng;
-use std::marker::PhantomData;
+use std::{marker::PhantomData, sync::Arc};
 use thiserror::Error;
 
 #[derive(Default)]
@@ -27,9 +27,10 @@ impl<T: HtnStateTrait> AssetLoader for HtnAssetLoader<T> {
         let mut value = String::new();
         reader.read_to_string(&mut value).await?;
         // info!("Loaded htn: {}", value);
+        // the seed is assigned from the `HtnRng` once loaded
         Ok(HtnAsset {
-            htn: parse_htn::<T>(&value).expect("Failed to parse htn"),
-            seed: rand::rng().random(),
+            htn: Arc::new(parse_htn::<T>(&value).expect("Failed to parse htn")),
+            seed: 0,
         })
     }
 
@@ -40,7 +41,9 @@ impl<T: HtnStateTrait> AssetLoader for HtnAssetLoader<T> {
 
 #[derive(Asset, TypePath)]
 pub struct HtnAsset<T: HtnStateTrait> {
-    pub htn: HTN<T>,
+    /// Shared so async planning can use the domain without copying it.
+    pub htn: Arc<HTN<T>>,
+    /// Random, from the `HtnRng`, to tell loads of the HTN apart. 0 until assigned.
     pub seed: u32,
 }
 
@@ -61,6 +64,37 @@ impl<T: HtnStateTrait> Plugin for HtnAssetPlugin<T> {
     fn build(&self, app: &mut App) {
         app.init_asset_loader::<HtnAssetLoader<T>>();
         app.init_asset::<HtnAsset<T>>();
+        app.init_resource::<HtnRng>();
+        app.add_systems(PreUpdate, prepare_htn_assets::<T>);
+    }
+}
+
+/// Verifies HTNs as they are loaded or reloaded, warning about any problems, and gives them a
+/// seed. They were compiled when they were built.
+fn prepare_htn_assets<T: HtnStateTrait>(
+    mut ev_asset: EventReader<AssetEvent<HtnAsset<T>>>,
+    mut assets: ResMut<Assets<HtnAsset<T>>>,
+    mut rng: ResMut<HtnRng>,
+    atr: Res<AppTypeRegistry>,
+) {
+    let state = T::default();
+    for ev in ev_asset.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = ev else {
+            continue;
+        };
+        // seeding modifies the asset, so check first to avoid handling our own event.
+        let Some(asset) = assets.get(*id) else {
+            continue;
+        };
+        if asset.seed != 0 {
+            continue;
+        }
+        if let Err(e) = asset.htn.verify_without_operators(&state, &atr) {
+            warn!("HTN {id:?} failed verification: {e}");
+        }
+        if let Some(asset) = assets.get_mut(*id) {
+            asset.seed = rng.random_range(1..=u32::MAX);
+        }
     }
 }
 
@@ -1,12 +1,20 @@
+mod agenda;
+mod claims;
 mod dsl;
 mod error;
 mod executor;
 mod htn;
 mod htn_assets;
+mod lifecycle;
+mod plan_tree;
 mod planner;
 mod reflect_operator;
+mod rng;
+mod scheduler;
 #[cfg(test)]
 mod tests;
+mod trace;
+mod world_state;
 
 /// Auto-implemented trait that HTN Planner state must abide by. Used as a trait alias.
 pub trait HtnStateTrait:
@@ -20,12 +28,20 @@ impl<
 }
 
 pub mod prelude {
+    pub use super::agenda::*;
+    pub use super::claims::*;
     pub use super::dsl::*;
     pub use super::executor::*;
     pub use super::htn::*;
     pub use super::htn_assets::*;
+    pub use super::lifecycle::*;
+    pub use super::plan_tree::*;
     pub use super::planner::*;
     pub use super::reflect_operator::*;
+    pub use super::rng::*;
+    pub use super::scheduler::*;
+    pub use super::trace::*;
+    pub use super::world_state::*;
     pub use super::HtnPlugin;
     pub use crate::error::HtnErr;
     pub use bevy_behave::prelude::*;
@@ -59,6 +75,39 @@ impl<T: HtnStateTrait> Plugin for HtnPlugin<T> {
         app.register_type::<PlannedTaskId>();
         app.register_type::<PlannedTask>();
         app.register_type::<Plan>();
+        app.register_type::<PlanTrace>();
+        app.register_type::<AsyncPlanning>();
+        app.register_type::<TimeSlicedPlanning>();
+        app.register_type::<MaxPlanningSteps>();
+        app.register_type::<PlanRepair>();
+        app.register_type::<DefaultTaskTimeout>();
+        app.register_type::<ReplanPriority>();
+        app.register_type::<ReplanPolicy>();
+        app.register_type::<HtnAgenda>();
+        app.register_type::<ClaimKey>();
+        app.register_type::<PlanIdCounter>();
+        app.register_type::<PlanStatus>();
+        app.register_type::<PlanHistory>();
+        app.init_resource::<HtnRng>();
+        if !app.is_plugin_added::<HtnSharedPlugin>() {
+            app.add_plugins(HtnSharedPlugin);
+        }
         app.add_plugins(executor::HtnExecutorPlugin::<T>::default());
     }
 }
+
+/// Claims, plan lifecycle reporting and task timing are shared by every state type, so they're
+/// only set up once, however many [`HtnPlugin`]s there are.
+struct HtnSharedPlugin;
+
+impl Plugin for HtnSharedPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HtnClaims>();
+        executor::add_shared_systems(app);
+        app.add_systems(Update, claims::release_finished_claims);
+        app.add_systems(Last, lifecycle::report_plan_transitions);
+        app.add_observer(claims::on_plan_claims);
+        app.add_observer(claims::on_plan_removed);
+        app.add_observer(lifecycle::on_plan_replaced);
+    }
+}
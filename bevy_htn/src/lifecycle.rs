@@ -0,0 +1,141 @@
+use crate::prelude::*;
+use bevy::prelude::*;
+
+/// Triggered on the supervisor entity each time its plan changes status.
+#[derive(Event, Debug, Clone, Reflect)]
+pub struct HtnPlanEvent {
+    pub plan_id: u32,
+    pub status: PlanStatus,
+}
+
+/// How many finished plans a [`PlanHistory`] keeps by default.
+pub const DEFAULT_PLAN_HISTORY_LEN: usize = 10;
+
+/// A plan that has finished, as kept in a [`PlanHistory`].
+#[derive(Reflect, Debug, Clone, PartialEq)]
+pub struct PlanRecord {
+    pub plan_id: u32,
+    pub tasks: Vec<String>,
+    pub agenda_item: Option<String>,
+    pub status: PlanStatus,
+}
+
+impl PlanRecord {
+    fn new(plan: &Plan) -> Self {
+        Self {
+            plan_id: plan.id(),
+            tasks: plan.task_names(),
+            agenda_item: plan.agenda_item().map(ToString::to_string),
+            status: plan.status(),
+        }
+    }
+}
+
+/// The supervisor's most recent finished plans, oldest first.
+///
+/// Finished plans stay on the supervisor as its `Plan` until replaced, but only a running plan
+/// is ever compared against a new one, so this is where to look for what happened before.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component, Default)]
+pub struct PlanHistory {
+    records: Vec<PlanRecord>,
+    max_len: usize,
+}
+
+impl Default for PlanHistory {
+    fn default() -> Self {
+        Self::with_max_len(DEFAULT_PLAN_HISTORY_LEN)
+    }
+}
+
+impl PlanHistory {
+    pub fn with_max_len(max_len: usize) -> Self {
+        Self {
+            records: Vec::new(),
+            max_len,
+        }
+    }
+
+    pub fn records(&self) -> &[PlanRecord] {
+        &self.records
+    }
+
+    /// The most recently finished plan.
+    pub fn last(&self) -> Option<&PlanRecord> {
+        self.records.last()
+    }
+
+    fn push(&mut self, record: PlanRecord) {
+        self.records.push(record);
+        if self.records.len() > self.max_len {
+            let excess = self.records.len() - self.max_len;
+            self.records.drain(..excess);
+        }
+    }
+}
+
+/// Triggers an event for each status change of the plan, and records it in the history once
+/// it finishes.
+fn report_transitions(
+    sup_entity: Entity,
+    plan: &mut Plan,
+    history: Option<&mut PlanHistory>,
+    commands: &mut Commands,
+) {
+    let transitions = plan.take_transitions();
+    if transitions.is_empty() {
+        return;
+    }
+    for &status in transitions.iter() {
+        commands.trigger_targets(
+            HtnPlanEvent {
+                plan_id: plan.id(),
+                status,
+            },
+            sup_entity,
+        );
+    }
+    if let Some(history) = history {
+        if transitions.iter().any(|status| status.is_finished()) {
+            history.push(PlanRecord::new(plan));
+        }
+    }
+}
+
+/// Reports plan status changes made during the frame.
+pub(crate) fn report_plan_transitions(
+    mut q: Query<(Entity, &mut Plan, Option<&mut PlanHistory>)>,
+    mut commands: Commands,
+) {
+    for (sup_entity, mut plan, history) in q.iter_mut() {
+        if !plan.has_transitions() {
+            continue;
+        }
+        // reporting isn't a change to the plan anything should react to
+        report_transitions(
+            sup_entity,
+            plan.bypass_change_detection(),
+            history.map(Mut::into_inner),
+            &mut commands,
+        );
+    }
+}
+
+/// A plan being replaced or removed is aborted if it was still active, and its remaining status
+/// changes are reported.
+pub(crate) fn on_plan_replaced(
+    t: Trigger<OnReplace, Plan>,
+    mut q: Query<(&mut Plan, Option<&mut PlanHistory>)>,
+    mut commands: Commands,
+) {
+    let Ok((mut plan, history)) = q.get_mut(t.entity()) else {
+        return;
+    };
+    plan.abort();
+    report_transitions(
+        t.entity(),
+        &mut plan,
+        history.map(Mut::into_inner),
+        &mut commands,
+    );
+}
@@ -0,0 +1,175 @@
+use crate::{htn::*, HtnStateTrait};
+use bevy::prelude::*;
+use std::ops::Range;
+
+/// How a task in a [`PlanTree`] was decomposed.
+#[derive(Reflect, Debug, Clone, PartialEq)]
+pub enum PlanNodeKind {
+    /// A compound task, and the method chosen to decompose it.
+    Compound {
+        method_index: usize,
+        method_name: Option<String>,
+    },
+    /// A primitive task, at `task_index` in the plan's tasks.
+    Primitive { task_index: usize },
+}
+
+#[derive(Reflect, Debug, Clone, PartialEq)]
+pub struct PlanNode {
+    pub task: TaskId,
+    pub name: String,
+    pub kind: PlanNodeKind,
+    pub parent: Option<usize>,
+    /// Indices of the subtasks of the chosen method, in order.
+    pub children: Vec<usize>,
+}
+
+/// The decomposition that produced a plan: compound task nodes, with the methods chosen for
+/// them, down to the primitive tasks the plan executes.
+///
+/// Nodes are stored in depth first order, so the primitives under a node are a contiguous
+/// range of the plan's tasks, see [`PlanTree::task_range`].
+#[derive(Reflect, Debug, Clone, Default, PartialEq)]
+pub struct PlanTree {
+    nodes: Vec<PlanNode>,
+    /// The tasks planning started with, usually just the root task.
+    roots: Vec<usize>,
+}
+
+impl PlanTree {
+    /// Builds the tree from the tasks the planner decomposed, in depth first order, with the
+    /// index of the method chosen for each compound task.
+    pub(crate) fn build<T: HtnStateTrait>(
+        htn: &HTN<T>,
+        decomposition: &[(TaskId, Option<usize>)],
+    ) -> Self {
+        let mut tree = Self::default();
+        // compound nodes still missing children, with how many they're missing
+        let mut open: Vec<(usize, usize)> = Vec::new();
+        let mut task_index = 0;
+        for &(task, method_index) in decomposition {
+            let node_index = tree.nodes.len();
+            let parent = open.last().map(|(parent, _)| *parent);
+            let htn_task = htn.get_task(task);
+            let (kind, child_count) = match (htn_task, method_index) {
+                (Some(Task::Compound(compound)), Some(method_index)) => {
+                    let method = &compound.methods[method_index];
+                    let kind = PlanNodeKind::Compound {
+                        method_index,
+                        method_name: method.name.clone(),
+                    };
+                    (kind, method.subtasks.len())
+                }
+                _ => {
+                    let kind = PlanNodeKind::Primitive { task_index };
+                    task_index += 1;
+                    (kind, 0)
+                }
+            };
+            tree.nodes.push(PlanNode {
+                task,
+                name: htn_task.map(|t| t.name().to_string()).unwrap_or_default(),
+                kind,
+                parent,
+                children: Vec::new(),
+            });
+            match parent {
+                Some(parent) => tree.nodes[parent].children.push(node_index),
+                None => tree.roots.push(node_index),
+            }
+            if let Some((_, missing)) = open.last_mut() {
+                *missing -= 1;
+            }
+            while open.last().is_some_and(|(_, missing)| *missing == 0) {
+                open.pop();
+            }
+            if child_count > 0 {
+                open.push((node_index, child_count));
+            }
+        }
+        tree
+    }
+
+    pub fn nodes(&self) -> &[PlanNode] {
+        &self.nodes
+    }
+
+    pub fn node(&self, index: usize) -> Option<&PlanNode> {
+        self.nodes.get(index)
+    }
+
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The index of the node for the primitive task at `task_index` in the plan.
+    pub fn task_node(&self, task_index: usize) -> Option<usize> {
+        self.nodes.iter().position(|node| {
+            matches!(node.kind, PlanNodeKind::Primitive { task_index: i } if i == task_index)
+        })
+    }
+
+    /// The compound task the primitive task at `task_index` in the plan was decomposed from.
+    pub fn parent_compound(&self, task_index: usize) -> Option<&PlanNode> {
+        let parent = self.nodes[self.task_node(task_index)?].parent?;
+        self.nodes.get(parent)
+    }
+
+    /// The ancestors of a node, starting with its parent.
+    pub fn ancestors(&self, index: usize) -> impl Iterator<Item = &PlanNode> {
+        std::iter::successors(self.node(index).and_then(|n| n.parent), |i| {
+            self.nodes[*i].parent
+        })
+        .map(|i| &self.nodes[i])
+    }
+
+    /// The indices in the plan's tasks of the primitive tasks under a node.
+    pub fn task_range(&self, index: usize) -> Range<usize> {
+        if index >= self.nodes.len() {
+            return 0..0;
+        }
+        // the subtree is the node and the nodes after it, up to the first that isn't a descendant
+        let end = self.nodes[index + 1..]
+            .iter()
+            .position(|node| !self.is_descendant(node, index))
+            .map_or(self.nodes.len(), |offset| index + 1 + offset);
+        let mut task_indices = self.nodes[index..end]
+            .iter()
+            .filter_map(|node| match node.kind {
+                PlanNodeKind::Primitive { task_index } => Some(task_index),
+                PlanNodeKind::Compound { .. } => None,
+            });
+        let Some(start) = task_indices.next() else {
+            return 0..0;
+        };
+        start..task_indices.next_back().unwrap_or(start) + 1
+    }
+
+    fn is_descendant(&self, node: &PlanNode, ancestor: usize) -> bool {
+        std::iter::successors(node.parent, |i| self.nodes[*i].parent).any(|i| i == ancestor)
+    }
+}
+
+impl std::fmt::Display for PlanTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for node in self.nodes.iter() {
+            let depth = std::iter::successors(node.parent, |i| self.nodes[*i].parent).count();
+            write!(f, "{:indent$}{}", "", node.name, indent = depth * 2)?;
+            match &node.kind {
+                PlanNodeKind::Compound {
+                    method_name: Some(method_name),
+                    ..
+                } => writeln!(f, " ({method_name})")?,
+                PlanNodeKind::Compound { method_index, .. } => {
+                    writeln!(f, " (method #{method_index})")?
+                }
+                PlanNodeKind::Primitive { .. } => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
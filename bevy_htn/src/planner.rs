@@ -1,7 +1,38 @@
-use crate::{htn::*, HtnStateTrait};
-use bevy::prelude::*;
-use rand::Rng;
-use std::collections::VecDeque;
+use crate::{
+    claims::{first_claimed_condition, ClaimKey},
+    error::HtnErr,
+    htn::*,
+    plan_tree::{PlanNodeKind, PlanTree},
+    trace::*,
+    HtnStateTrait,
+};
+use bevy::{prelude::*, utils::HashSet};
+use std::{ops::ControlFlow, sync::Arc};
+
+/// Where a plan is in its lifecycle. Plans start pending, run once their first task starts, and
+/// end up succeeded, failed, or aborted.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlanStatus {
+    #[default]
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    /// Stopped before finishing, eg. because it was invalidated or replaced.
+    Aborted,
+}
+
+impl PlanStatus {
+    /// True if the plan is pending or running.
+    pub fn is_active(self) -> bool {
+        matches!(self, PlanStatus::Pending | PlanStatus::Running)
+    }
+
+    /// True if the plan succeeded, failed or was aborted.
+    pub fn is_finished(self) -> bool {
+        !self.is_active()
+    }
+}
 
 #[derive(Reflect, Debug, Component)]
 pub struct Plan {
@@ -9,19 +40,43 @@ pub struct Plan {
     next_task_index: usize,
     pub tasks: Vec<PlannedTask>,
     mtr: Vec<usize>,
-    status: Option<bool>,
+    status: PlanStatus,
+    // status changes not yet reported as `HtnPlanEvent`s
+    #[reflect(ignore)]
+    transitions: Vec<PlanStatus>,
+    trace: Option<PlanTrace>,
+    agenda_item: Option<String>,
+    claims: Vec<ClaimKey>,
+    tree: PlanTree,
+    // the state the planner predicted once every task has completed, and the values each task's
+    // changes overwrote, so the states before that can be recovered, see `predicted_state`
+    #[reflect(ignore)]
+    final_state: Option<Arc<dyn Reflect>>,
+    #[reflect(ignore)]
+    overwritten: Vec<Vec<(usize, Box<dyn PartialReflect>)>>,
 }
 
 impl Plan {
-    pub fn new(tasks: Vec<String>, mtr: Vec<usize>) -> Self {
-        let plan_id = rand::rng().random::<u32>();
+    /// Plans start with id 0, and are given an id by the supervisor they're inserted on.
+    pub fn new<T: HtnStateTrait>(htn: &HTN<T>, tasks: Vec<TaskId>, mtr: Vec<usize>) -> Self {
+        let plan_id = 0;
         let tasks = tasks
-            .iter()
+            .into_iter()
             .enumerate()
-            .map(|(idx, name)| PlannedTask {
-                name: name.clone(),
-                status: TaskStatus::NotStarted,
-                id: PlannedTaskId::new(plan_id, idx, name.clone()),
+            .map(|(idx, task)| {
+                let name = htn
+                    .get_task(task)
+                    .map(|t| t.name().to_string())
+                    .unwrap_or_default();
+                PlannedTask {
+                    id: PlannedTaskId::new(plan_id, idx, name.clone()),
+                    task,
+                    name,
+                    status: TaskStatus::NotStarted,
+                    duration: None,
+                    predicted_changes: Vec::new(),
+                    retries: 0,
+                }
             })
             .collect();
         Self {
@@ -29,7 +84,14 @@ impl Plan {
             next_task_index: 0,
             tasks,
             mtr,
-            status: None,
+            status: PlanStatus::Pending,
+            transitions: vec![PlanStatus::Pending],
+            trace: None,
+            agenda_item: None,
+            claims: Vec::new(),
+            tree: PlanTree::default(),
+            final_state: None,
+            overwritten: Vec::new(),
         }
     }
     // pub fn preconditions_met<T: HtnStateTrait>(&self, state: &T, atr: &AppTypeRegistry) -> bool {
@@ -46,37 +108,161 @@ impl Plan {
     pub fn id(&self) -> u32 {
         self.plan_id
     }
+
+    /// Sets the plan id, and the ids of its tasks to match.
+    pub(crate) fn assign_id(&mut self, plan_id: u32) {
+        self.plan_id = plan_id;
+        for task in self.tasks.iter_mut() {
+            task.id.plan_id = plan_id;
+        }
+    }
     pub fn mtr(&self) -> &[usize] {
         &self.mtr
     }
-    /// None = pending, Some(true) = success, Some(false) = failure
-    pub fn status(&self) -> Option<bool> {
+    pub fn status(&self) -> PlanStatus {
         self.status
     }
 
+    fn set_status(&mut self, status: PlanStatus) {
+        self.status = status;
+        self.transitions.push(status);
+    }
+
+    pub(crate) fn has_transitions(&self) -> bool {
+        !self.transitions.is_empty()
+    }
+
+    /// Removes the status changes since the last call, oldest first.
+    pub(crate) fn take_transitions(&mut self) -> Vec<PlanStatus> {
+        std::mem::take(&mut self.transitions)
+    }
+
+    /// The decomposition trace recorded by the planner, if tracing was enabled.
+    pub fn trace(&self) -> Option<&PlanTrace> {
+        self.trace.as_ref()
+    }
+
+    /// Removes the trace from the plan, eg. to insert it as a component.
+    pub fn take_trace(&mut self) -> Option<PlanTrace> {
+        self.trace.take()
+    }
+
+    /// The task from the supervisor's `HtnAgenda` this plan is for, or None if it's for the root
+    /// task.
+    pub fn agenda_item(&self) -> Option<&str> {
+        self.agenda_item.as_deref()
+    }
+
+    pub(crate) fn for_agenda_item(mut self, agenda_item: Option<String>) -> Self {
+        self.agenda_item = agenda_item;
+        self
+    }
+
+    /// The values claimed by `claim` effects of tasks in this plan, see `HtnClaims`.
+    pub fn claims(&self) -> &[ClaimKey] {
+        &self.claims
+    }
+
+    /// The decomposition tree the plan's tasks came from. Empty for plans that weren't made by
+    /// an [`HtnPlanner`].
+    pub fn tree(&self) -> &PlanTree {
+        &self.tree
+    }
+
+    /// Sets the estimated duration of each task, in order.
+    pub fn with_durations(mut self, durations: impl IntoIterator<Item = Option<f32>>) -> Self {
+        for (task, duration) in self.tasks.iter_mut().zip(durations) {
+            task.duration = duration;
+        }
+        self
+    }
+
+    /// The state the planner predicted once the task at `index`, and every task before it, has
+    /// completed. None if the plan wasn't made by the planner, or `T` isn't its state type.
+    ///
+    /// Only the fields each task changes are kept, so this clones the predicted final state and
+    /// undoes the changes of the tasks after `index`.
+    pub fn predicted_state<T: HtnStateTrait>(&self, index: usize) -> Option<T> {
+        if index >= self.overwritten.len() {
+            return None;
+        }
+        let mut state = self.final_state.as_ref()?.downcast_ref::<T>()?.clone();
+        let reflected = state.reflect_mut().as_struct().ok()?;
+        for overwritten in self.overwritten[index + 1..].iter().rev() {
+            for (field, old) in overwritten.iter() {
+                reflected
+                    .field_at_mut(*field)?
+                    .try_apply(old.as_ref())
+                    .ok()?;
+            }
+        }
+        Some(state)
+    }
+
+    /// Estimated time to execute the whole plan in seconds. Tasks without a duration count as 0.
+    pub fn estimated_duration(&self) -> f32 {
+        self.tasks.iter().filter_map(|t| t.duration).sum()
+    }
+
+    /// Estimated time to execute the tasks that haven't finished yet, in seconds.
+    pub fn estimated_remaining_duration(&self) -> f32 {
+        self.tasks
+            .iter()
+            .filter(|t| matches!(t.status, TaskStatus::NotStarted | TaskStatus::Running))
+            .filter_map(|t| t.duration)
+            .sum()
+    }
+
+    /// Stops a pending or running plan, finished plans are left as they are.
     pub fn abort(&mut self) {
-        self.status = Some(false);
+        if self.status.is_active() {
+            self.set_status(PlanStatus::Aborted);
+        }
     }
 
     pub fn task_names(&self) -> Vec<String> {
         self.tasks.iter().map(|t| t.name.clone()).collect()
     }
 
-    /// Iterates over primitive tasks in plan, checking working_state preconditions met, then
-    /// applying effects and checking next task, etc.
+    /// Returns the planned task with the given id, if it belongs to this plan.
+    pub fn task(&self, task_id: &PlannedTaskId) -> Option<&PlannedTask> {
+        if task_id.plan_id != self.plan_id {
+            return None;
+        }
+        self.tasks.get(task_id.index)
+    }
+
+    /// Iterates over the running and remaining primitive tasks in plan, checking working_state
+    /// preconditions met, then applying effects and checking next task, etc.
+    ///
+    /// Tasks that already completed are skipped, as their effects are already applied to the
+    /// state. The running task may have already brought about its own effects, so its
+    /// preconditions are checked with [`PrimitiveTask::preconditions_met_while_running`].
     pub fn check_validity<T: HtnStateTrait>(
         &self,
         htn: &HTN<T>,
         mut working_state: T,
         atr: &AppTypeRegistry,
     ) -> bool {
-        for task_name in self.tasks.iter() {
-            let task = htn.get_task_by_name(task_name.name.as_str()).unwrap();
+        for planned_task in self
+            .tasks
+            .iter()
+            .filter(|t| matches!(t.status, TaskStatus::NotStarted | TaskStatus::Running))
+        {
+            let Some(task) = htn.get_task(planned_task.task) else {
+                info!("Plan invalidated, task not in HTN: {}", planned_task.name);
+                return false;
+            };
             if let Task::Primitive(task) = task {
-                if !task.preconditions_met(&working_state, atr) {
+                let preconditions_met = if planned_task.status == TaskStatus::Running {
+                    task.preconditions_met_while_running(&working_state, atr)
+                } else {
+                    task.preconditions_met(&working_state, atr)
+                };
+                if !preconditions_met {
                     info!(
                         "Plan invalidated, preconditions not met: {} `{}`",
-                        task_name.name,
+                        planned_task.name,
                         task.find_first_failing_precondition(&working_state, atr)
                             .map(|c| c.syntax())
                             .unwrap_or("???".to_string())
@@ -92,25 +278,116 @@ impl Plan {
         true
     }
 
+    /// The state fields that could change the outcome of planning again, or None if the plan has
+    /// no decomposition tree to tell. Fields of nested structs are named by their full path, like
+    /// `world.coins`.
+    ///
+    /// These are the fields read by methods with a higher priority than the ones chosen for this
+    /// plan, and by every task those methods could decompose into, as a change to any of them
+    /// might let a better method succeed. Also the fields read by the tasks still to run, which
+    /// might make the plan invalid. Fields are read by conditions, and by effects that copy or
+    /// add one field to another.
+    pub fn relevant_fields<T: HtnStateTrait>(&self, htn: &HTN<T>) -> Option<HashSet<String>> {
+        if self.tree.is_empty() {
+            return None;
+        }
+        let mut methods: Vec<&Method<T>> = Vec::new();
+        for node in self.tree.nodes() {
+            if let (PlanNodeKind::Compound { method_index, .. }, Some(Task::Compound(compound))) =
+                (&node.kind, htn.get_task(node.task))
+            {
+                methods.extend(compound.methods[..*method_index].iter());
+            }
+        }
+        // everything the higher priority methods could decompose into
+        let mut unchosen: Vec<TaskId> = methods
+            .iter()
+            .flat_map(|method| method.subtask_ids.iter().flatten())
+            .copied()
+            .collect();
+        let mut visited: HashSet<TaskId> = HashSet::new();
+        let mut primitives = Vec::new();
+        while let Some(task) = unchosen.pop() {
+            if !visited.insert(task) {
+                continue;
+            }
+            match htn.get_task(task) {
+                Some(Task::Compound(compound)) => {
+                    for method in compound.methods.iter() {
+                        methods.push(method);
+                        unchosen.extend(method.subtask_ids.iter().flatten());
+                    }
+                }
+                Some(Task::Primitive(primitive)) => primitives.push(primitive),
+                None => {}
+            }
+        }
+        primitives.extend(
+            self.tasks
+                .iter()
+                .filter(|t| matches!(t.status, TaskStatus::NotStarted | TaskStatus::Running))
+                .filter_map(|t| match htn.get_task(t.task) {
+                    Some(Task::Primitive(primitive)) => Some(primitive),
+                    _ => None,
+                }),
+        );
+        let mut conditions: Vec<&HtnCondition> = methods
+            .into_iter()
+            .flat_map(|method| method.preconditions.iter())
+            .collect();
+        let mut effects: Vec<&Effect> = Vec::new();
+        for primitive in primitives {
+            conditions.extend(primitive.preconditions.iter());
+            effects.extend(
+                primitive
+                    .effects
+                    .iter()
+                    .chain(primitive.expected_effects.iter()),
+            );
+        }
+        Some(
+            conditions
+                .into_iter()
+                .flat_map(|cond| std::iter::once(cond.field()).chain(cond.other_field()))
+                .chain(effects.into_iter().filter_map(Effect::source_field))
+                .map(str::to_string)
+                .collect(),
+        )
+    }
+
     /// Marks next task as running and returns the planned task id
     pub fn next_task_to_execute(&mut self) -> Option<PlannedTaskId> {
-        if self.status.is_some() {
-            warn!("Plan already has a status, cannot execute next task.");
+        if !self.status.is_active() {
+            warn!("Plan is {:?}, cannot execute next task.", self.status);
             return None;
         }
         if self.next_task_index >= self.tasks.len() {
             info!("Plan complete, no next task.");
             return None;
         }
+        if self.status == PlanStatus::Pending {
+            self.set_status(PlanStatus::Running);
+        }
         let task = &mut self.tasks[self.next_task_index];
         task.status = TaskStatus::Running;
         self.next_task_index += 1;
         Some(task.id.clone())
     }
 
+    /// Counts a retry of a running task that failed, returning how many times it's been retried.
+    pub(crate) fn count_retry(&mut self, task_id: &PlannedTaskId) -> usize {
+        match self.tasks.get_mut(task_id.index) {
+            Some(task) if task.id == *task_id => {
+                task.retries += 1;
+                task.retries
+            }
+            _ => 0,
+        }
+    }
+
     pub fn report_task_completion(&mut self, task_id: &PlannedTaskId, success: bool) {
-        if self.status.is_some() {
-            warn!("Plan already has a status, cannot report task completion.");
+        if !self.status.is_active() {
+            warn!("Plan is {:?}, cannot report task completion.", self.status);
             return;
         }
         if let Some((idx, task)) = self
@@ -128,7 +405,7 @@ impl Plan {
             } else {
                 task.status = TaskStatus::Failure;
                 warn!("Task {task:?} failed, plan failed.");
-                self.status = Some(false);
+                self.set_status(PlanStatus::Failed);
                 return;
             }
             self.next_task_index = idx + 1;
@@ -137,7 +414,7 @@ impl Plan {
         }
         if self.next_task_index >= self.tasks.len() {
             info!("Plan completed!");
-            self.status = Some(true);
+            self.set_status(PlanStatus::Succeeded);
         }
     }
 }
@@ -225,8 +502,17 @@ impl PlannedTaskId {
 #[derive(Reflect, Clone, Debug)]
 pub struct PlannedTask {
     pub id: PlannedTaskId,
+    /// The task in the HTN this plan was made from.
+    pub task: TaskId,
     pub name: String,
     pub status: TaskStatus,
+    /// Estimated time to execute the task in seconds, if it has a duration.
+    pub duration: Option<f32>,
+    /// The changes to the state the planner predicted this task would make, from its effects and
+    /// expected effects.
+    pub predicted_changes: Vec<FieldChange>,
+    /// How many times the task has been started again after failing.
+    pub retries: usize,
 }
 
 #[derive(Reflect, Debug, Clone, PartialEq)]
@@ -237,184 +523,850 @@ pub enum TaskStatus {
     Failure,
 }
 
+/// The stack of tasks still to be processed, top of stack last.
+///
+/// Every push and pop is recorded in a trail, so the stack can be unwound to how it was at a
+/// decomposition without keeping a copy of it.
+#[derive(Debug, Default)]
+struct TaskStack {
+    tasks: Vec<TaskId>,
+    trail: Vec<TaskStackOp>,
+}
+
+#[derive(Debug)]
+enum TaskStackOp {
+    Pushed,
+    Popped(TaskId),
+}
+
+impl TaskStack {
+    fn push(&mut self, task: TaskId) {
+        self.tasks.push(task);
+        self.trail.push(TaskStackOp::Pushed);
+    }
+
+    fn pop(&mut self) -> Option<TaskId> {
+        let task = self.tasks.pop()?;
+        self.trail.push(TaskStackOp::Popped(task));
+        Some(task)
+    }
+
+    fn clear(&mut self) {
+        self.tasks.clear();
+        self.trail.clear();
+    }
+
+    /// Reverts pushes and pops made since the trail was `trail_len` long, most recent first.
+    fn unwind(&mut self, trail_len: usize) {
+        while self.trail.len() > trail_len {
+            match self.trail.pop().unwrap() {
+                TaskStackOp::Pushed => {
+                    self.tasks.pop();
+                }
+                TaskStackOp::Popped(task) => self.tasks.push(task),
+            }
+        }
+    }
+}
+
+/// The value of a state field before an effect overwrote it.
+#[derive(Debug)]
+struct UndoEntry {
+    field: FieldPath,
+    previous: UndoValue,
+}
+
+/// Field types the DSL can set with literals are stored inline, to avoid boxing a clone.
+#[derive(Debug)]
+enum UndoValue {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    Reflect(Box<dyn PartialReflect>),
+}
+
+impl UndoValue {
+    fn new(value: &dyn PartialReflect) -> Self {
+        if let Some(b) = value.try_downcast_ref::<bool>() {
+            UndoValue::Bool(*b)
+        } else if let Some(i) = value.try_downcast_ref::<i32>() {
+            UndoValue::Int(*i)
+        } else if let Some(f) = value.try_downcast_ref::<f32>() {
+            UndoValue::Float(*f)
+        } else {
+            UndoValue::Reflect(value.clone_value())
+        }
+    }
+
+    fn restore(self, field: &mut dyn PartialReflect) {
+        match self {
+            UndoValue::Bool(b) => field.apply(&b),
+            UndoValue::Int(i) => field.apply(&i),
+            UndoValue::Float(f) => field.apply(&f),
+            UndoValue::Reflect(previous) => field.apply(previous.as_ref()),
+        }
+    }
+}
+
+/// Everything needed to backtrack to a compound task and try its next method.
+///
+/// The plan, MTR, undo log and task stack trail only grow until we backtrack past this point, so
+/// recording their lengths is enough to restore them.
 #[derive(Debug)]
 struct DecompositionState {
-    current_task: String,
-    final_plan: Vec<String>,
+    current_task: TaskId,
     skip_methods: usize,
-    mtr: Vec<usize>,
+    plan_len: usize,
+    mtr_len: usize,
+    undo_len: usize,
+    trail_len: usize,
+    claims_len: usize,
+    decomposition_len: usize,
 }
 
-pub struct HtnPlanner<'a, T: HtnStateTrait> {
-    htn: &'a HTN<T>,
-    task_stack: VecDeque<String>,
+/// Planning fails after this many steps unless [`HtnPlanner::with_max_steps`] says otherwise, in
+/// case of logic errors in the domain, like compound tasks that recurse forever.
+pub const DEFAULT_MAX_STEPS: usize = 10_000;
+
+/// The search state of an [`HtnPlanner`] part way through planning.
+///
+/// It doesn't borrow the HTN, so it can be kept between frames (eg. in a component) and planning
+/// continued later with [`HtnPlanner::resume`].
+/// Applies the task's effects and expected effects to the state, returning the index and previous
+/// value of each top level field that changed, and describing the changes in `changes`.
+fn predict_changes<T: HtnStateTrait>(
+    primitive: &PrimitiveTask<T>,
+    state: &mut T,
+    atr: &AppTypeRegistry,
+    changes: &mut Vec<FieldChange>,
+) -> Vec<(usize, Box<dyn PartialReflect>)> {
+    let Ok(reflected) = state.reflect_ref().as_struct() else {
+        return Vec::new();
+    };
+    let mut overwritten: Vec<(usize, Box<dyn PartialReflect>)> = Vec::new();
+    for effect in primitive
+        .effects
+        .iter()
+        .chain(primitive.expected_effects.iter())
+    {
+        if matches!(effect, Effect::Claim { .. }) {
+            continue;
+        }
+        let Some(&field) = FieldPath::resolve(reflected, effect.field())
+            .as_ref()
+            .and_then(|path| path.indices().first())
+        else {
+            continue;
+        };
+        if overwritten.iter().all(|(idx, _)| *idx != field) {
+            if let Some(old) = reflected.field_at(field) {
+                overwritten.push((field, old.clone_value()));
+            }
+        }
+    }
+    primitive.apply_effects(state, atr);
+    primitive.apply_expected_effects(state, atr);
+    let Ok(reflected) = state.reflect_ref().as_struct() else {
+        return Vec::new();
+    };
+    overwritten.retain(|(field, old)| {
+        let Some(new) = reflected.field_at(*field) else {
+            return false;
+        };
+        if old.reflect_partial_eq(new).unwrap_or(false) {
+            return false;
+        }
+        changes.push(FieldChange {
+            field: reflected.name_at(*field).unwrap_or("???").to_string(),
+            old: format!("{old:?}"),
+            new: format!("{new:?}"),
+        });
+        true
+    });
+    overwritten
+}
+
+pub struct PlannerProgress<T: HtnStateTrait> {
+    // the state planning started from
+    initial_state: T,
+    // the working state, with effects of tasks in the plan so far applied
+    state: T,
+    task_stack: TaskStack,
     decomp_stack: Vec<DecompositionState>,
     skip_methods: usize,
-    atr: &'a AppTypeRegistry,
     mtr: Vec<usize>,
+    // previous values of fields changed by effects, so backtracking can roll back the working
+    // state without cloning it for every decomposition.
+    undo_log: Vec<UndoEntry>,
+    // tasks in the plan so far, with their estimated durations
+    final_plan: Vec<(TaskId, Option<f32>)>,
+    // compound and primitive tasks decomposed so far in depth first order, with the method
+    // chosen for compound tasks, to build the plan's tree from
+    decomposition: Vec<(TaskId, Option<usize>)>,
+    // values claimed by tasks in the plan so far
+    claims: Vec<ClaimKey>,
+    // values claimed by other supervisors, which `unclaimed` conditions fail for
+    claimed: HashSet<ClaimKey>,
+    steps: usize,
+    // planning fails after this many steps, for `plan_alternatives`
+    step_limit: Option<usize>,
+    // planning fails after this many steps, whatever the step limit
+    max_steps: usize,
+    planning: bool,
+    // true if planning couldn't find a way to decompose the tasks it started with
+    failed: bool,
+    trace: Option<PlanTrace>,
 }
 
-impl<'a, T: HtnStateTrait> HtnPlanner<'a, T> {
-    pub fn new(htn: &'a HTN<T>, atr: &'a AppTypeRegistry) -> Self {
+impl<T: HtnStateTrait> Default for PlannerProgress<T> {
+    fn default() -> Self {
         Self {
-            task_stack: VecDeque::new(),
-            htn,
+            initial_state: T::default(),
+            state: T::default(),
+            task_stack: TaskStack::default(),
             decomp_stack: Vec::new(),
             skip_methods: 0,
-            atr,
             mtr: Vec::new(),
+            undo_log: Vec::new(),
+            final_plan: Vec::new(),
+            decomposition: Vec::new(),
+            claims: Vec::new(),
+            claimed: HashSet::default(),
+            steps: 0,
+            step_limit: None,
+            max_steps: DEFAULT_MAX_STEPS,
+            planning: false,
+            failed: false,
+            trace: None,
         }
     }
+}
+
+impl<T: HtnStateTrait> PlannerProgress<T> {
+    /// True if planning has started and not yet produced a plan.
+    pub fn is_planning(&self) -> bool {
+        self.planning
+    }
+
+    /// The number of steps run since planning started.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
 
     fn reset(&mut self) {
         self.decomp_stack.clear();
         self.task_stack.clear();
         self.skip_methods = 0;
         self.mtr.clear();
+        self.undo_log.clear();
+        self.final_plan.clear();
+        self.decomposition.clear();
+        self.claims.clear();
+        self.steps = 0;
+        self.step_limit = None;
+        self.failed = false;
+        if let Some(trace) = self.trace.as_mut() {
+            trace.steps.clear();
+        }
+    }
+
+    fn record(&mut self, step: impl FnOnce() -> TraceStep) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.push(step());
+        }
+    }
+
+    /// Applies a primitive task's effects and expected effects to the working state, recording the
+    /// previous value of each field they change.
+    fn apply_effects(&mut self, primitive: &PrimitiveTask<T>, atr: &AppTypeRegistry) {
+        if let Some(effects) = primitive.compiled_effects() {
+            let reflected = self
+                .state
+                .reflect_mut()
+                .as_struct()
+                .expect("State is not a struct");
+            for effect in effects {
+                record_undo(&mut self.undo_log, reflected, effect.field());
+                effect.apply(reflected);
+            }
+            return;
+        }
+        for effect in primitive
+            .effects
+            .iter()
+            .chain(primitive.expected_effects.iter())
+        {
+            let reflected = self
+                .state
+                .reflect_mut()
+                .as_struct()
+                .expect("State is not a struct");
+            if let Some(field) = FieldPath::resolve(reflected, effect.field()) {
+                record_undo(&mut self.undo_log, reflected, &field);
+            }
+            effect.apply(&mut self.state, atr);
+        }
+    }
+
+    /// Rolls back effects applied since the undo log was `undo_len` long, most recent first.
+    fn rollback(&mut self, undo_len: usize) {
+        let reflected = self
+            .state
+            .reflect_mut()
+            .as_struct()
+            .expect("State is not a struct");
+        while self.undo_log.len() > undo_len {
+            let entry = self.undo_log.pop().unwrap();
+            if let Some(field) = entry.field.get_mut(reflected) {
+                entry.previous.restore(field);
+            }
+        }
+    }
+
+    /// Restores the most recent decomposition, to try its next method. Breaks if there are no
+    /// decompositions left to try.
+    fn backtrack(&mut self, htn: &HTN<T>) -> ControlFlow<()> {
+        if let Some(decomp) = self.decomp_stack.pop() {
+            debug!("Restoring decomp {decomp:?}");
+            self.record(|| TraceStep::Backtrack {
+                task: htn
+                    .get_task(decomp.current_task)
+                    .map(|t| t.name().to_string())
+                    .unwrap_or_default(),
+                skip_methods: decomp.skip_methods,
+            });
+            self.final_plan.truncate(decomp.plan_len);
+            self.decomposition.truncate(decomp.decomposition_len);
+            self.claims.truncate(decomp.claims_len);
+            self.mtr.truncate(decomp.mtr_len);
+            self.rollback(decomp.undo_len);
+            self.skip_methods = decomp.skip_methods;
+            self.task_stack.unwind(decomp.trail_len);
+            self.task_stack.push(decomp.current_task);
+            ControlFlow::Continue(())
+        } else {
+            debug!("No decomp, plan failed");
+            self.final_plan.clear();
+            self.decomposition.clear();
+            self.claims.clear();
+            self.failed = true;
+            self.mtr.clear();
+            ControlFlow::Break(())
+        }
+    }
+
+    /// Records each method evaluated by `find_method`, along with the first failing precondition.
+    fn trace_methods(&mut self, compound: &CompoundTask<T>, atr: &AppTypeRegistry) {
+        let Some(trace) = self.trace.as_mut() else {
+            return;
+        };
+        for (method_index, method) in compound.methods.iter().enumerate().skip(self.skip_methods) {
+            let failed_condition = method
+                .find_first_failing_precondition(&self.state, atr)
+                .or_else(|| {
+                    first_claimed_condition(&method.preconditions, &self.state, &self.claimed)
+                })
+                .map(|c| c.syntax());
+            let chosen = failed_condition.is_none();
+            trace.push(TraceStep::TryMethod {
+                task: compound.name.clone(),
+                method_index,
+                method_name: method.name.clone(),
+                failed_condition,
+            });
+            if chosen {
+                break;
+            }
+        }
+    }
+}
+
+fn record_undo(undo_log: &mut Vec<UndoEntry>, state: &dyn Struct, field: &FieldPath) {
+    if let Some(previous) = field.get(state) {
+        undo_log.push(UndoEntry {
+            field: field.clone(),
+            previous: UndoValue::new(previous),
+        });
+    }
+}
+
+pub struct HtnPlanner<'a, T: HtnStateTrait> {
+    htn: &'a HTN<T>,
+    atr: &'a AppTypeRegistry,
+    progress: PlannerProgress<T>,
+}
+
+impl<'a, T: HtnStateTrait> HtnPlanner<'a, T> {
+    pub fn new(htn: &'a HTN<T>, atr: &'a AppTypeRegistry) -> Self {
+        Self::resume(htn, atr, PlannerProgress::default())
+    }
+
+    /// Continues planning from progress returned by [`HtnPlanner::into_progress`].
+    /// `htn` must be the same domain planning was started with.
+    pub fn resume(htn: &'a HTN<T>, atr: &'a AppTypeRegistry, progress: PlannerProgress<T>) -> Self {
+        Self { htn, atr, progress }
+    }
+
+    /// Returns the planner's search state, so planning can be resumed later.
+    pub fn into_progress(self) -> PlannerProgress<T> {
+        self.progress
+    }
+
+    /// Record a [`PlanTrace`] of every decision made while planning, returned with the [`Plan`].
+    pub fn with_trace(mut self) -> Self {
+        self.progress.trace = Some(PlanTrace::default());
+        self
+    }
+
+    /// Fail planning after this many steps, rather than [`DEFAULT_MAX_STEPS`]. Each step
+    /// evaluates one task, and backtracking means tasks may be evaluated more than once, so deep
+    /// domains with lots of methods may need more.
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.progress.max_steps = max_steps;
+        self
+    }
+
+    /// Values claimed by other supervisors, which `unclaimed` conditions should fail for.
+    /// See `HtnClaims::claimed_by_others`.
+    pub fn with_claimed(mut self, claimed: HashSet<ClaimKey>) -> Self {
+        self.progress.claimed = claimed;
+        self
+    }
+
+    /// True if planning has started and not yet produced a plan.
+    pub fn is_planning(&self) -> bool {
+        self.progress.planning
     }
 
     pub fn plan(&mut self, initial_state: &T) -> Plan {
-        const SANITY_LIMIT: usize = 100;
-        let mut sanity_count = 0;
-        self.reset();
-        let mut final_plan = Vec::new();
-        self.task_stack
-            .push_back(self.htn.root_task().name().to_string());
-        let mut state = initial_state.clone();
-        // debug!("PLAN initial state: {state:?}");
-        // Using vecdeque as a stack, top of stack (next item) is the FRONT
-        while let Some(current_task_name) = self.task_stack.pop_front() {
-            sanity_count += 1;
-            if sanity_count > SANITY_LIMIT {
-                // in case of logic errors during dev..
-                error!("Sanity limit reached, aborting");
+        self.start(initial_state);
+        self.plan_steps(usize::MAX)
+            .expect("Planning always finishes within the step limit")
+    }
+
+    /// Finds up to `max_plans` different plans for the root task, in priority order (by MTR),
+    /// by continuing to backtrack after each plan is found.
+    ///
+    /// Stops early once `max_steps` planning steps have been run in total, so searching large
+    /// domains is bounded. Decompositions that produce the same tasks as an earlier plan are
+    /// skipped.
+    pub fn plan_alternatives(
+        &mut self,
+        initial_state: &T,
+        max_plans: usize,
+        max_steps: usize,
+    ) -> Vec<Plan> {
+        let mut plans: Vec<Plan> = Vec::new();
+        self.start(initial_state);
+        self.progress.step_limit = Some(max_steps);
+        while plans.len() < max_plans {
+            let plan = self
+                .plan_steps(usize::MAX)
+                .expect("Planning always finishes within the step limit");
+            if self.progress.failed {
                 break;
             }
-            let Some(task) = self.htn.get_task_by_name(&current_task_name) else {
-                error!("Task {current_task_name} not found in HTN");
-                final_plan.clear();
+            if !plans.contains(&plan) {
+                plans.push(plan);
+            }
+            // treat the plan we found as a failure, to try the next method
+            self.progress.planning = true;
+            if self.progress.backtrack(self.htn).is_break() {
+                self.progress.planning = false;
                 break;
+            }
+        }
+        plans
+    }
+
+    /// Finds the plan for the root task with the shortest estimated duration, out of up to
+    /// `max_plans` alternatives, see [`HtnPlanner::plan_alternatives`].
+    /// If several are equally fast, the highest priority one is returned.
+    pub fn plan_fastest(
+        &mut self,
+        initial_state: &T,
+        max_plans: usize,
+        max_steps: usize,
+    ) -> Option<Plan> {
+        self.plan_alternatives(initial_state, max_plans, max_steps)
+            .into_iter()
+            .reduce(|fastest, plan| {
+                if plan.estimated_duration() < fastest.estimated_duration() {
+                    plan
+                } else {
+                    fastest
+                }
+            })
+    }
+
+    /// Plans for the named tasks in order, starting from `initial_state`, instead of the root task.
+    ///
+    /// Returns an error if any of the tasks aren't in the HTN, or if they can't all be decomposed
+    /// into primitive tasks with passing preconditions.
+    pub fn plan_tasks(&mut self, initial_state: &T, tasks: &[&str]) -> Result<Plan, HtnErr> {
+        let task_ids = tasks
+            .iter()
+            .map(|name| {
+                self.htn.task_id(name).ok_or_else(|| HtnErr::UnknownTask {
+                    task: name.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.start_with_tasks(initial_state, &task_ids);
+        let plan = self
+            .plan_steps(usize::MAX)
+            .expect("Planning always finishes within the step limit");
+        if self.progress.failed {
+            return Err(HtnErr::Planning {
+                details: format!("No plan found for {tasks:?}"),
+            });
+        }
+        Ok(plan)
+    }
+
+    /// Plans for a single named task, see [`HtnPlanner::plan_tasks`].
+    pub fn plan_task(&mut self, initial_state: &T, task: &str) -> Result<Plan, HtnErr> {
+        self.plan_tasks(initial_state, &[task])
+    }
+
+    /// Repairs `plan` after the task at `failed_task` in it failed, by trying the methods after
+    /// the one chosen for the compound task it was decomposed from, starting from `state`, and
+    /// following them with the plan's tasks after that compound task's.
+    ///
+    /// The repaired plan keeps the agenda item of the plan it repairs. Its MTR is the plan's, up
+    /// to the repaired compound task, followed by the methods chosen while repairing, so it ranks
+    /// below plans that could use a method the failed one skipped. Its tree starts from the
+    /// repaired compound task, followed by the remaining primitive tasks. Returns an
+    /// error if the failed task wasn't decomposed from a compound task, or none of the remaining
+    /// methods lead to a plan.
+    pub fn repair(&mut self, plan: &Plan, failed_task: usize, state: &T) -> Result<Plan, HtnErr> {
+        let tree = plan.tree();
+        let Some((parent_index, parent)) = tree
+            .task_node(failed_task)
+            .and_then(|node| tree.node(node)?.parent)
+            .and_then(|parent| Some((parent, tree.node(parent)?)))
+        else {
+            return Err(HtnErr::Planning {
+                details: format!("Task #{failed_task} has no compound task to repair"),
+            });
+        };
+        let PlanNodeKind::Compound { method_index, .. } = parent.kind else {
+            unreachable!("Only compound tasks have children");
+        };
+        let mut tasks = vec![parent.task];
+        tasks.extend(
+            plan.tasks[tree.task_range(parent_index).end..]
+                .iter()
+                .map(|t| t.task),
+        );
+        self.start_with_tasks(state, &tasks);
+        self.progress.skip_methods = method_index + 1;
+        let repaired = self
+            .plan_steps(usize::MAX)
+            .expect("Planning always finishes within the step limit");
+        if self.progress.failed {
+            return Err(HtnErr::Planning {
+                details: format!("No other method of {} leads to a plan", parent.name),
+            });
+        }
+        let mut repaired = repaired.for_agenda_item(plan.agenda_item.clone());
+        // the MTR has an entry per compound task, in the order the tree's nodes are in
+        let mtr_prefix = tree.nodes()[..parent_index]
+            .iter()
+            .filter(|node| matches!(node.kind, PlanNodeKind::Compound { .. }))
+            .count();
+        repaired.mtr = plan.mtr[..mtr_prefix]
+            .iter()
+            .chain(repaired.mtr.iter())
+            .copied()
+            .collect();
+        Ok(repaired)
+    }
+
+    /// Starts planning from `initial_state`, without running any steps.
+    /// Call [`HtnPlanner::plan_steps`] to make progress.
+    pub fn start(&mut self, initial_state: &T) {
+        self.start_with_tasks(initial_state, &[self.htn.root_task_id()]);
+    }
+
+    /// Starts planning for `tasks` in order, instead of the root task.
+    pub fn start_with_tasks(&mut self, initial_state: &T, tasks: &[TaskId]) {
+        self.progress.reset();
+        self.progress.initial_state.clone_from(initial_state);
+        self.progress.state.clone_from(initial_state);
+        // the first task goes on top of the stack
+        for task in tasks.iter().rev() {
+            self.progress.task_stack.push(*task);
+        }
+        self.progress.planning = true;
+        // debug!("PLAN initial state: {state:?}");
+    }
+
+    /// True if the last planning run couldn't find a complete plan.
+    pub fn failed(&self) -> bool {
+        self.progress.failed
+    }
+
+    /// Runs at most `max_steps` steps of planning, returning the plan once it's finished.
+    ///
+    /// Each step evaluates one task from the task stack, so planning can be spread over several
+    /// frames with a fixed amount of work per frame.
+    pub fn plan_steps(&mut self, max_steps: usize) -> Option<Plan> {
+        if !self.progress.planning {
+            warn!("Call HtnPlanner::start before running planning steps");
+            return None;
+        }
+        for _ in 0..max_steps {
+            if self.step().is_break() {
+                return Some(self.finish());
+            }
+        }
+        // the last step may have completed the plan
+        self.progress
+            .task_stack
+            .tasks
+            .is_empty()
+            .then(|| self.finish())
+    }
+
+    fn finish(&mut self) -> Plan {
+        let progress = &mut self.progress;
+        progress.planning = false;
+        debug!("Planning final state: {:#?}", progress.state);
+        // not taken, in case we backtrack from here to look for alternatives
+        let (final_plan, durations): (Vec<_>, Vec<_>) = progress.final_plan.iter().copied().unzip();
+        let mut plan =
+            Plan::new(self.htn, final_plan, progress.mtr.clone()).with_durations(durations);
+        // replay the plan's effects, rather than cloning the working state for every primitive
+        // tried while planning, keeping what each task changed
+        let mut state = progress.initial_state.clone();
+        for task in plan.tasks.iter_mut() {
+            let overwritten = match self.htn.get_task(task.task) {
+                Some(Task::Primitive(primitive)) => {
+                    predict_changes(primitive, &mut state, self.atr, &mut task.predicted_changes)
+                }
+                _ => Vec::new(),
             };
+            plan.overwritten.push(overwritten);
+        }
+        plan.final_state = Some(Arc::new(state));
+        info!("final plan: {plan}");
+        plan.trace = progress.trace.clone();
+        plan.claims = progress.claims.clone();
+        if !progress.failed {
+            plan.tree = PlanTree::build(self.htn, &progress.decomposition);
+            debug!("Plan tree:\n{}", plan.tree);
+        }
+        plan
+    }
 
-            // if let Some(top_task) = self.decomp_stack.last().map(|d| d.current_task.clone()) {
-            //     if top_task == current_task_name {
-            //         info!("Resetting skip_methods to 0 for {current_task_name}");
-            //         self.skip_methods = 0;
-            //     }
-            // }
-
-            // info!(
-            //     "Processing: {current_task_name} Stack: {:?}",
-            //     self.task_stack
-            // );
-            debug!(
-                "EVALUATING {current_task_name} with self.skip_methods = {}",
-                self.skip_methods
-            );
-            debug!(" planner state: {state:?}");
-            debug!(" decomp stack len: {:?}", self.decomp_stack.len());
-            match task {
-                Task::Compound(compound) => {
-                    // find the first method with passing preconditions
-
-                    // for (method_index, method) in
-                    //     compound.methods.iter().enumerate().skip(self.skip_methods)
-                    // {
-                    //     let valid = method
-                    //         .preconditions
-                    //         .iter()
-                    //         .all(|cond| cond.evaluate(&state, self.atr));
-                    //     if !valid {
-                    //         info!(
-                    //             "🟥 {current_task_name} method: {method_index} - {} (skipped {}) PRECONDITIONS NOT MET",
-                    //             method
-                    //                 .name
-                    //                 .clone()
-                    //                 .unwrap_or_else(|| format!("#{method_index}")),
-                    //             self.skip_methods,
-                    //         );
-                    //         continue;
-                    //     }
-                    // }
-
-                    if let Some((method, method_index)) =
-                        compound.find_method(&state, self.skip_methods, self.atr)
+    /// Evaluates the task on top of the task stack, breaking once planning has finished.
+    fn step(&mut self) -> ControlFlow<()> {
+        let htn = self.htn;
+        let atr = self.atr;
+        let progress = &mut self.progress;
+        let Some(current_task) = progress.task_stack.pop() else {
+            return ControlFlow::Break(());
+        };
+        progress.steps += 1;
+        match progress.step_limit {
+            Some(limit) if progress.steps > limit => {
+                debug!("Step limit reached, aborting");
+                progress.failed = true;
+                return ControlFlow::Break(());
+            }
+            _ if progress.steps > progress.max_steps => {
+                error!(
+                    "Planning gave up after {} steps, see HtnPlanner::with_max_steps",
+                    progress.max_steps
+                );
+                progress.failed = true;
+                return ControlFlow::Break(());
+            }
+            _ => {}
+        }
+        let Some(task) = htn.get_task(current_task) else {
+            error!("Task {current_task:?} not found in HTN");
+            progress.final_plan.clear();
+            progress.failed = true;
+            return ControlFlow::Break(());
+        };
+        let current_task_name = task.name();
+
+        // if let Some(top_task) = self.decomp_stack.last().map(|d| d.current_task.clone()) {
+        //     if top_task == current_task_name {
+        //         info!("Resetting skip_methods to 0 for {current_task_name}");
+        //         self.skip_methods = 0;
+        //     }
+        // }
+
+        // info!(
+        //     "Processing: {current_task_name} Stack: {:?}",
+        //     self.task_stack
+        // );
+        debug!(
+            "EVALUATING {current_task_name} with self.skip_methods = {}",
+            progress.skip_methods
+        );
+        debug!(" planner state: {:?}", progress.state);
+        debug!(" decomp stack len: {:?}", progress.decomp_stack.len());
+        progress.record(|| TraceStep::VisitTask {
+            task: current_task_name.to_string(),
+        });
+        match task {
+            Task::Compound(compound) => {
+                if progress.trace.is_some() {
+                    progress.trace_methods(compound, atr);
+                }
+
+                // find the first method with passing preconditions
+
+                // for (method_index, method) in
+                //     compound.methods.iter().enumerate().skip(self.skip_methods)
+                // {
+                //     let valid = method
+                //         .preconditions
+                //         .iter()
+                //         .all(|cond| cond.evaluate(&state, self.atr));
+                //     if !valid {
+                //         info!(
+                //             "🟥 {current_task_name} method: {method_index} - {} (skipped {}) PRECONDITIONS NOT MET",
+                //             method
+                //                 .name
+                //                 .clone()
+                //                 .unwrap_or_else(|| format!("#{method_index}")),
+                //             self.skip_methods,
+                //         );
+                //         continue;
+                //     }
+                // }
+
+                // methods whose `unclaimed` conditions fail are skipped too
+                let mut skip = progress.skip_methods;
+                let found = loop {
+                    match compound.find_method(&progress.state, skip, atr) {
+                        Some((method, method_index))
+                            if first_claimed_condition(
+                                &method.preconditions,
+                                &progress.state,
+                                &progress.claimed,
+                            )
+                            .is_some() =>
+                        {
+                            skip = method_index + 1;
+                        }
+                        found => break found,
+                    }
+                };
+                if let Some((method, method_index)) = found {
+                    debug!(
+                        "🟨 {current_task_name} -> {} (using index: {method_index}, skipped {})",
+                        method
+                            .name
+                            .clone()
+                            .unwrap_or_else(|| format!("#{method_index}")),
+                        progress.skip_methods,
+                    );
+                    // record decomposition, as it was before choosing this method
+                    let decomposition = DecompositionState {
+                        current_task,
+                        // method index 0 based, skip is number to skip:
+                        skip_methods: method_index + 1,
+                        plan_len: progress.final_plan.len(),
+                        mtr_len: progress.mtr.len(),
+                        undo_len: progress.undo_log.len(),
+                        trail_len: progress.task_stack.trail.len(),
+                        claims_len: progress.claims.len(),
+                        decomposition_len: progress.decomposition.len(),
+                    };
+                    progress.mtr.push(method_index);
+                    progress
+                        .decomposition
+                        .push((current_task, Some(method_index)));
+                    debug!("📚 Adding {decomposition:?}");
+                    progress.decomp_stack.push(decomposition);
+                    // add subtasks to the stack, preserving order
+                    for (subtask, subtask_id) in
+                        method.subtasks.iter().zip(method.subtask_ids.iter()).rev()
                     {
-                        debug!(
-                            "🟨 {current_task_name} -> {} (using index: {method_index}, skipped {})",
-                            method
-                                .name
-                                .clone()
-                                .unwrap_or_else(|| format!("#{method_index}")),
-                            self.skip_methods,
-                        );
-                        self.mtr.push(method_index);
-                        // record decomposition
-                        let decomposition = DecompositionState {
-                            current_task: current_task_name.clone(),
-                            final_plan: final_plan.clone(),
-                            // method index 0 based, skip is number to skip:
-                            skip_methods: method_index + 1,
-                            mtr: self.mtr.clone(),
+                        let Some(subtask_id) = subtask_id else {
+                            error!("Task {subtask} not found in HTN");
+                            progress.final_plan.clear();
+                            progress.failed = true;
+                            progress.mtr.clear();
+                            return ControlFlow::Break(());
                         };
-                        debug!("📚 Adding {decomposition:?}");
-                        self.decomp_stack.push(decomposition);
-                        // add subtasks to the stack, preserving order
-                        for subtask in method.subtasks.iter().rev() {
-                            self.task_stack.push_front(subtask.clone());
-                        }
-                        debug!("🟡 Adding decomposed tasks to plan: {:?}", method.subtasks);
-                        // do we need to reset the skip_methods when recursively calling ourself?
-
-                        // info!("💫 Resetting skip_methods to 0 for {current_task_name}");
-                        self.skip_methods = 0;
-                        // how? TODO
-                        continue;
-                    } else {
-                        debug!(
-                            "🟥 Compound task {current_task_name} has no valid method, skip: {} \nstate was {state:?}",
-                            self.skip_methods
-                        );
-                        // info!("Current state: {state:?}");
-                        // fall through to restore decomp
+                        progress.task_stack.push(*subtask_id);
                     }
+                    debug!("🟡 Adding decomposed tasks to plan: {:?}", method.subtasks);
+                    // do we need to reset the skip_methods when recursively calling ourself?
+
+                    // info!("💫 Resetting skip_methods to 0 for {current_task_name}");
+                    progress.skip_methods = 0;
+                    // how? TODO
+                    return ControlFlow::Continue(());
+                } else {
+                    debug!(
+                        "🟥 Compound task {current_task_name} has no valid method, skip: {} \nstate was {:?}",
+                        progress.skip_methods, progress.state
+                    );
+                    // info!("Current state: {state:?}");
+                    // fall through to restore decomp
                 }
-                Task::Primitive(primitive) => {
-                    if primitive.preconditions_met(&state, self.atr) {
-                        debug!(
-                            "🟢 Adding primitive task to plan: {current_task_name} -> [{}]",
-                            final_plan.join(", ")
-                        );
-                        // add task to final plan
-                        final_plan.push(current_task_name);
-                        // apply this task's effects to the planner state
-                        for effect in primitive.effects.iter() {
-                            effect.apply(&mut state, self.atr);
-                        }
-                        for effect in primitive.expected_effects.iter() {
-                            effect.apply(&mut state, self.atr);
+            }
+            Task::Primitive(primitive) => {
+                let claimed = first_claimed_condition(
+                    &primitive.preconditions,
+                    &progress.state,
+                    &progress.claimed,
+                );
+                if claimed.is_none() && primitive.preconditions_met(&progress.state, atr) {
+                    debug!("🟢 Adding primitive task to plan: {current_task_name}");
+                    let state_before = progress.trace.is_some().then(|| progress.state.clone());
+                    // estimated using the state the task will start executing in
+                    let duration = primitive.estimate_duration(&progress.state);
+                    for effect in primitive.effects.iter() {
+                        if let Effect::Claim { field, .. } = effect {
+                            progress
+                                .claims
+                                .extend(ClaimKey::from_field(&progress.state, field));
                         }
-                        // debug!("Working state is now: {state:?}");
-                        continue;
-                    } else {
-                        debug!("🔴 Primitive task preconditions not met: {current_task_name}\nstate was: {state:?}");
-                        // info!("Current state: {state:?}");
-                        // fall through to restore decomp
                     }
+                    // apply this task's effects to the planner state
+                    progress.apply_effects(primitive, atr);
+                    if let Some(state_before) = state_before {
+                        let state_diff = state_diff(&state_before, &progress.state);
+                        progress.record(|| TraceStep::AddPrimitive {
+                            task: current_task_name.to_string(),
+                            state_diff,
+                        });
+                    }
+                    // add task to final plan
+                    progress.final_plan.push((current_task, duration));
+                    progress.decomposition.push((current_task, None));
+                    // debug!("Working state is now: {state:?}");
+                    return ControlFlow::Continue(());
+                } else {
+                    debug!(
+                        "🔴 Primitive task preconditions not met: {current_task_name}\nstate was: {:?}",
+                        progress.state
+                    );
+                    if progress.trace.is_some() {
+                        let failed_condition = claimed
+                            .or_else(|| {
+                                primitive.find_first_failing_precondition(&progress.state, atr)
+                            })
+                            .map(|c| c.syntax());
+                        progress.record(|| TraceStep::RejectPrimitive {
+                            task: current_task_name.to_string(),
+                            failed_condition,
+                        });
+                    }
+                    // info!("Current state: {state:?}");
+                    // fall through to restore decomp
                 }
             }
-            if let Some(decomp) = self.decomp_stack.pop() {
-                debug!("Restoring decomp {decomp:?}");
-                final_plan = decomp.final_plan;
-                self.skip_methods = decomp.skip_methods;
-                self.task_stack.push_front(decomp.current_task);
-                self.mtr = decomp.mtr;
-            } else {
-                debug!("No decomp, plan failed?");
-            }
         }
-        debug!("Planning final state: {state:#?}");
-        info!("final plan: {final_plan:?} mtr: {:?}", self.mtr);
-        Plan::new(final_plan, self.mtr.clone())
+        progress.backtrack(htn)
     }
 }
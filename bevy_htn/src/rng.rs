@@ -0,0 +1,20 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, SeedableRng};
+
+/// The source of randomness for everything HTN related, eg. the seeds given to loaded
+/// `HtnAsset`s. Seeded from the OS by default, insert one made with [`HtnRng::seeded`] so runs of
+/// the same scenario are reproducible.
+#[derive(Resource, Debug, Clone, Deref, DerefMut)]
+pub struct HtnRng(StdRng);
+
+impl HtnRng {
+    pub fn seeded(seed: u64) -> Self {
+        Self(StdRng::seed_from_u64(seed))
+    }
+}
+
+impl Default for HtnRng {
+    fn default() -> Self {
+        Self(StdRng::from_os_rng())
+    }
+}
@@ -0,0 +1,281 @@
+use crate::{executor::*, prelude::*, HtnStateTrait};
+use bevy::{
+    ecs::entity::Entities,
+    prelude::*,
+    utils::{Duration, HashMap, Instant},
+};
+
+/// Insert this resource to queue `ReplanRequest`s instead of planning as soon as they're
+/// triggered, and spread the planning over several frames.
+///
+/// Queued supervisors are planned for in `PostUpdate`, highest [`ReplanPriority`] first, until
+/// `max_plans_per_frame` or `max_time_per_frame` is reached. Remaining requests wait for the next
+/// frame. Requesting a replan for a supervisor that is already queued doesn't queue it again.
+///
+/// Supervisors using [`AsyncPlanning`] or [`TimeSlicedPlanning`] count towards the budget when
+/// their planning is started, rather than when it finishes.
+#[derive(Resource, Debug, Default)]
+pub struct ReplanScheduler {
+    /// Maximum number of plans per frame, or None for no limit.
+    pub max_plans_per_frame: Option<usize>,
+    /// Maximum time spent planning per frame, or None for no limit.
+    /// Checked before each plan, so a slow plan can exceed it.
+    pub max_time_per_frame: Option<Duration>,
+    // queued supervisors, with the order they were first queued in
+    queue: HashMap<Entity, u64>,
+    next_seq: u64,
+    plans_this_frame: usize,
+    time_this_frame: Duration,
+}
+
+impl ReplanScheduler {
+    pub fn with_max_plans_per_frame(mut self, max_plans: usize) -> Self {
+        self.max_plans_per_frame = Some(max_plans);
+        self
+    }
+
+    pub fn with_max_time_per_frame(mut self, max_time: Duration) -> Self {
+        self.max_time_per_frame = Some(max_time);
+        self
+    }
+
+    /// Queues a replan for the supervisor, unless it's already queued.
+    pub fn enqueue(&mut self, sup_entity: Entity) {
+        if self.queue.contains_key(&sup_entity) {
+            debug!("Replan already queued for {sup_entity:?}");
+            return;
+        }
+        self.queue.insert(sup_entity, self.next_seq);
+        self.next_seq += 1;
+    }
+
+    pub fn is_queued(&self, sup_entity: Entity) -> bool {
+        self.queue.contains_key(&sup_entity)
+    }
+
+    /// Number of supervisors waiting to replan.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Number of plans made so far this frame.
+    pub fn plans_this_frame(&self) -> usize {
+        self.plans_this_frame
+    }
+
+    fn has_budget(&self) -> bool {
+        self.max_plans_per_frame
+            .is_none_or(|max| self.plans_this_frame < max)
+            && self
+                .max_time_per_frame
+                .is_none_or(|max| self.time_this_frame < max)
+    }
+}
+
+/// The priority of a supervisor's queued replans when using a [`ReplanScheduler`], higher first.
+/// Supervisors without one have priority 0. Requests with the same priority are handled in the
+/// order they were queued.
+///
+/// Update it as often as you like, eg. based on distance to the camera. It's read when the queue
+/// is processed.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy, PartialEq, PartialOrd)]
+#[reflect(Component, Default)]
+pub struct ReplanPriority(pub f32);
+
+/// When a supervisor replans. Supervisors without one use the default, which replans whenever
+/// the state changes, and when the plan finishes.
+///
+/// Modes can be combined, eg. to replan when the plan is invalidated, or every 2 seconds:
+/// `ReplanPolicy { interval: Some(2.0), ..ReplanPolicy::on_invalidation() }`
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(Component, Default)]
+#[require(ReplanTiming)]
+pub struct ReplanPolicy {
+    /// Replan whenever the state changes, even if the plan is still valid. Only changes to fields
+    /// that could change the plan count, see [`Plan::relevant_fields`].
+    pub on_change: bool,
+    /// Replan when the state changes such that the plan is no longer valid.
+    pub on_invalidation: bool,
+    /// Replan when the plan succeeds or fails.
+    pub on_finish: bool,
+    /// Replan every this many seconds.
+    pub interval: Option<f32>,
+    /// Replans requested within this many seconds of the last one wait until it has passed, and
+    /// requests made while waiting are merged.
+    pub min_interval: f32,
+}
+
+impl Default for ReplanPolicy {
+    fn default() -> Self {
+        Self {
+            on_finish: true,
+            ..Self::on_change()
+        }
+    }
+}
+
+impl ReplanPolicy {
+    const NEVER: Self = Self {
+        on_change: false,
+        on_invalidation: false,
+        on_finish: false,
+        interval: None,
+        min_interval: 0.0,
+    };
+
+    pub fn on_change() -> Self {
+        Self {
+            on_change: true,
+            on_invalidation: true,
+            ..Self::NEVER
+        }
+    }
+
+    pub fn on_invalidation() -> Self {
+        Self {
+            on_invalidation: true,
+            ..Self::NEVER
+        }
+    }
+
+    pub fn on_finish() -> Self {
+        Self {
+            on_finish: true,
+            ..Self::NEVER
+        }
+    }
+
+    pub fn every(secs: f32) -> Self {
+        Self {
+            interval: Some(secs),
+            ..Self::NEVER
+        }
+    }
+
+    pub fn with_min_interval(mut self, secs: f32) -> Self {
+        self.min_interval = secs;
+        self
+    }
+
+    pub(crate) fn replans_on_invalidation(&self) -> bool {
+        self.on_change || self.on_invalidation
+    }
+}
+
+/// When a supervisor with a [`ReplanPolicy`] last replanned, and whether a replan is waiting for
+/// its `min_interval`.
+#[derive(Component, Debug, Default)]
+pub(crate) struct ReplanTiming {
+    last_replan: Option<f32>,
+    pending: bool,
+}
+
+/// Records a replan request for a supervisor, returning false if its [`ReplanPolicy`] says it
+/// should wait for the `min_interval` since its last replan to pass.
+pub(crate) fn allow_replan(
+    q: &mut Query<(&ReplanPolicy, &mut ReplanTiming)>,
+    sup_entity: Entity,
+    now: f32,
+) -> bool {
+    let Ok((policy, mut timing)) = q.get_mut(sup_entity) else {
+        return true;
+    };
+    if timing
+        .last_replan
+        .is_some_and(|last| now - last < policy.min_interval)
+    {
+        debug!("Replan for {sup_entity:?} requested too soon, waiting");
+        timing.pending = true;
+        return false;
+    }
+    timing.last_replan = Some(now);
+    timing.pending = false;
+    true
+}
+
+/// Requests replans that were waiting for their `min_interval`, or are due by their `interval`.
+pub(crate) fn replan_on_schedule<T: HtnStateTrait>(
+    q: Query<(Entity, &ReplanPolicy, &ReplanTiming), With<HtnSupervisor<T>>>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    let now = time.elapsed_secs();
+    for (sup_entity, policy, timing) in q.iter() {
+        let since_last = timing.last_replan.map_or(f32::INFINITY, |last| now - last);
+        let waited = timing.pending && since_last >= policy.min_interval;
+        let due = policy
+            .interval
+            .is_some_and(|interval| since_last >= interval);
+        if waited || due {
+            commands.trigger_targets(ReplanRequest, sup_entity);
+        }
+    }
+}
+
+pub(crate) fn reset_replan_budget(scheduler: Option<ResMut<ReplanScheduler>>) {
+    if let Some(mut scheduler) = scheduler {
+        scheduler.plans_this_frame = 0;
+        scheduler.time_this_frame = Duration::ZERO;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn process_replan_queue<T: HtnStateTrait>(
+    scheduler: Option<ResMut<ReplanScheduler>>,
+    mut q: Query<ReplanData<T>>,
+    q_priority: Query<&ReplanPriority>,
+    entities: &Entities,
+    assets: Res<Assets<HtnAsset<T>>>,
+    claims: Res<HtnClaims>,
+    atr: Res<AppTypeRegistry>,
+    mut commands: Commands,
+) {
+    let Some(mut scheduler) = scheduler else {
+        return;
+    };
+    if scheduler.is_empty() {
+        return;
+    }
+    // forget supervisors that have been despawned
+    scheduler
+        .queue
+        .retain(|entity, _| entities.contains(*entity));
+    // the queue is shared by every state type, so only take our own supervisors
+    let mut queued = scheduler
+        .queue
+        .iter()
+        .filter(|(entity, _)| q.contains(**entity))
+        .map(|(entity, seq)| {
+            let priority = q_priority.get(*entity).map(|p| p.0).unwrap_or_default();
+            (*entity, priority, *seq)
+        })
+        .collect::<Vec<_>>();
+    queued.sort_by(|(_, a_pri, a_seq), (_, b_pri, b_seq)| {
+        b_pri.total_cmp(a_pri).then(a_seq.cmp(b_seq))
+    });
+    for (sup_entity, _, _) in queued {
+        if !scheduler.has_budget() {
+            debug!(
+                "Replan budget used up, {} supervisors still waiting",
+                scheduler.len()
+            );
+            break;
+        }
+        scheduler.queue.remove(&sup_entity);
+        let start = Instant::now();
+        replan(
+            sup_entity,
+            &mut q,
+            &assets,
+            &claims,
+            atr.as_ref(),
+            &mut commands,
+        );
+        scheduler.plans_this_frame += 1;
+        scheduler.time_this_frame += start.elapsed();
+    }
+}
@@ -1,5 +1,5 @@
-use crate::prelude::*;
-use bevy::prelude::*;
+use crate::{prelude::*, HtnStateTrait};
+use bevy::{ecs::system::RunSystemOnce, prelude::*};
 trait AppTestExt {
     fn atr(&self) -> &AppTypeRegistry;
 }
@@ -51,6 +51,90 @@ fn setup_app() -> App {
     app
 }
 
+/// An app with the plugins for supervisors of `T`, and `TestOperator1` registered.
+fn htn_app<T: HtnStateTrait>() -> App {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        AssetPlugin::default(),
+        HtnAssetPlugin::<T>::default(),
+        HtnPlugin::<T>::default(),
+    ));
+    app.register_type::<TestOperator1>();
+    app
+}
+
+/// Each update advances time by 100ms.
+fn fixed_frame_time(app: &mut App) {
+    app.insert_resource(bevy::time::TimeUpdateStrategy::ManualDuration(
+        std::time::Duration::from_millis(100),
+    ));
+}
+
+/// Adds the HTN as an asset, as if it had been loaded.
+fn add_htn<T: HtnStateTrait>(app: &mut App, htn: HTN<T>) -> Handle<HtnAsset<T>> {
+    app.world_mut()
+        .resource_mut::<Assets<HtnAsset<T>>>()
+        .add(HtnAsset {
+            htn: std::sync::Arc::new(htn),
+            seed: 0,
+        })
+}
+
+fn spawn_supervisor<T: HtnStateTrait>(
+    app: &mut App,
+    htn_handle: &Handle<HtnAsset<T>>,
+    state: &T,
+) -> Entity {
+    let character = app.world_mut().spawn_empty().id();
+    let sup = app
+        .world_mut()
+        .commands()
+        .entity(character)
+        .spawn_htn_supervisor(htn_handle.clone(), state);
+    app.world_mut().flush();
+    sup
+}
+
+/// Plans for the supervisor straight away.
+fn replan(app: &mut App, sup: Entity) {
+    app.world_mut().trigger_targets(ReplanRequest, sup);
+    app.world_mut().flush();
+}
+
+/// Reports that the first task of the supervisor's plan has finished.
+fn complete_first_task(app: &mut App, sup: Entity, success: bool) {
+    let task_id = app.world().get::<Plan>(sup).unwrap().tasks[0].id.clone();
+    app.world_mut()
+        .trigger_targets(TaskComplete::new(task_id, success), sup);
+    app.world_mut().flush();
+}
+
+/// How many times a replan has been requested, once [`count_replans`] is set up.
+#[derive(Resource, Default)]
+struct ReplanRequests(usize);
+
+fn count_replans(app: &mut App) {
+    app.init_resource::<ReplanRequests>();
+    app.add_observer(
+        |_t: Trigger<ReplanRequest>, mut requests: ResMut<ReplanRequests>| requests.0 += 1,
+    );
+}
+
+fn replan_requests(app: &App) -> usize {
+    app.world().resource::<ReplanRequests>().0
+}
+
+/// Parses and verifies an HTN for planning outside of an app, with `T` in its own registry.
+fn planner_htn<T: HtnStateTrait>(src: &str) -> (HTN<T>, AppTypeRegistry) {
+    let atr = AppTypeRegistry::default();
+    atr.write().register::<T>();
+    let htn = parse_htn::<T>(src).expect("Failed to parse htn");
+    htn.verify_without_operators(&T::default(), &atr)
+        .expect("Failed to verify htn");
+    (htn, atr)
+}
+
 #[test]
 fn test_set_bool() {
     let app = setup_app();
@@ -427,6 +511,298 @@ fn test_travel_htn() {
     }
 }
 
+/// The domain from `test_travel_htn`, with durations, for the tests of what else plans can tell us.
+mod travel {
+    use super::planner_htn;
+    use crate::prelude::*;
+    use bevy::prelude::*;
+
+    #[derive(Reflect, Default, Clone, Debug, PartialEq, Eq)]
+    #[reflect(Default)]
+    pub enum Location {
+        #[default]
+        Home,
+        Other,
+        Park,
+    }
+
+    #[derive(Reflect, Clone, Debug, Default, Component)]
+    #[reflect(Default)]
+    pub struct TravelState {
+        pub cash: i32,
+        pub distance_to_park: i32,
+        pub happy: bool,
+        pub my_location: Location,
+        pub taxi_location: Location,
+    }
+
+    impl TravelState {
+        pub fn at_home(distance_to_park: i32) -> Self {
+            Self {
+                cash: 10,
+                distance_to_park,
+                happy: false,
+                my_location: Location::Home,
+                taxi_location: Location::Other,
+            }
+        }
+    }
+
+    pub fn htn() -> (HTN<TravelState>, AppTypeRegistry) {
+        planner_htn::<TravelState>(
+            r#"
+    schema {
+        version: 0.1.0
+    }
+
+    compound_task "TravelToPark" {
+        method {
+            subtasks: [ Walk ]
+        }
+        method {
+            subtasks: [ Taxi ]
+        }
+    }
+
+    primitive_task "Walk" {
+        operator: WalkOperator
+        // one second per unit of distance
+        duration: distance_to_park
+        preconditions: [distance_to_park <= 4, my_location != Location::Park, happy == false]
+        effects: [my_location = Location::Park, happy = true]
+    }
+
+    compound_task "Taxi" {
+        method {
+            subtasks: [CallTaxi, RideTaxi, PayTaxi]
+        }
+    }
+
+    primitive_task "CallTaxi" {
+        operator: TaxiOperator
+        duration: 1
+        preconditions: [cash >= 1]
+        effects: [taxi_location = my_location]
+    }
+
+    primitive_task "RideTaxi" {
+        operator: RideTaxiOperator(distance_to_park)
+        duration: 1.0
+        preconditions: [taxi_location == my_location, cash >= 1]
+        effects: [taxi_location = Location::Park, my_location = Location::Park, happy = true]
+    }
+
+    primitive_task "PayTaxi" {
+        operator: PayTaxiOperator
+        duration: 0.5
+        preconditions: [taxi_location == Location::Park, cash >= 1]
+        effects: [cash -= 1]
+    }
+    "#,
+        )
+    }
+}
+
+#[test]
+fn test_task_ids() {
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    primitive_task "TestTask1" {
+        operator: TestOperator1
+    }
+    compound_task "CompoundTask1" {
+        method {
+            subtasks: [TestTask1]
+        }
+        method {
+            subtasks: [FooTask, BarTask]
+        }
+    }
+    "#;
+    let htn = parse_htn::<TestState>(src).expect("Failed to parse htn");
+    let Task::Compound(task2) = &htn.tasks[1] else {
+        panic!("Task is not a compound");
+    };
+
+    // subtasks are resolved to task ids when built, unknown tasks fail verification.
+    let task1_id = htn.task_id("TestTask1").expect("TestTask1 has an id");
+    assert_eq!(htn.task_id("CompoundTask1").map(|id| id.index()), Some(1));
+    assert_eq!(htn.task_id("FooTask"), None);
+    assert_eq!(htn.get_task(task1_id).unwrap().name(), "TestTask1");
+    assert_eq!(task2.methods[0].subtask_ids, vec![Some(task1_id)]);
+    assert_eq!(task2.methods[1].subtask_ids, vec![None, None]);
+    assert!(matches!(
+        htn.verify_subtasks(),
+        Err(HtnErr::Subtask { task, subtask }) if task == "CompoundTask1" && subtask == "FooTask"
+    ));
+
+    // planned tasks know which task they are
+    let (htn, atr) = travel::htn();
+    let plan = HtnPlanner::new(&htn, &atr).plan(&travel::TravelState::at_home(5));
+    assert!(plan
+        .tasks
+        .iter()
+        .all(|t| htn.task_id(&t.name) == Some(t.task)));
+}
+
+#[test]
+fn test_durations() {
+    // durations must be numbers, or numeric state fields
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    primitive_task "None" {
+        operator: TestOperator1
+    }
+    primitive_task "Fixed" {
+        operator: TestOperator1
+        duration: 2.5
+    }
+    primitive_task "FromState" {
+        operator: TestOperator1
+        duration: counter
+    }
+    primitive_task "NotANumber" {
+        operator: TestOperator1
+        duration: location
+    }
+    "#;
+    let htn = parse_htn::<TestState>(src).expect("Failed to parse htn");
+    let durations = htn
+        .tasks
+        .iter()
+        .map(|t| match t {
+            Task::Primitive(p) => p.duration.clone(),
+            Task::Compound(_) => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        durations,
+        vec![
+            None,
+            Some(TaskDuration::Fixed(2.5)),
+            Some(TaskDuration::Field("counter".to_string())),
+            Some(TaskDuration::Field("location".to_string())),
+        ]
+    );
+    assert!(matches!(
+        htn.verify_durations(&TestState::default()),
+        Err(HtnErr::Duration { task, .. }) if task == "NotANumber"
+    ));
+
+    // plans add up the durations of their tasks
+    let (htn, atr) = travel::htn();
+    let mut planner = HtnPlanner::new(&htn, &atr);
+    let plan = planner.plan(&travel::TravelState::at_home(1));
+    assert_eq!(plan.task_names(), vec!["Walk"]);
+    assert_eq!(plan.estimated_duration(), 1.0);
+    let mut plan = planner.plan(&travel::TravelState::at_home(5));
+    assert_eq!(plan.task_names(), vec!["CallTaxi", "RideTaxi", "PayTaxi"]);
+    assert_eq!(plan.estimated_duration(), 2.5);
+    let task_id = plan.next_task_to_execute().unwrap();
+    plan.report_task_completion(&task_id, true);
+    assert_eq!(plan.estimated_remaining_duration(), 1.5);
+}
+
+#[test]
+fn test_predicted_state() {
+    use travel::{Location, TravelState};
+
+    let (htn, atr) = travel::htn();
+    let plan = HtnPlanner::new(&htn, &atr).plan(&TravelState::at_home(5));
+    assert_eq!(plan.task_names(), vec!["CallTaxi", "RideTaxi", "PayTaxi"]);
+    // the state expected after each step
+    let after_ride = plan.predicted_state::<TravelState>(1).unwrap();
+    assert_eq!(after_ride.my_location, Location::Park);
+    assert_eq!(after_ride.cash, 10);
+    assert_eq!(plan.predicted_state::<TravelState>(2).unwrap().cash, 9);
+    // earlier states are recovered by undoing the changes of the tasks after them
+    let after_call = plan.predicted_state::<TravelState>(0).unwrap();
+    assert_eq!(after_call.taxi_location, Location::Home);
+    assert_eq!(after_call.my_location, Location::Home);
+    assert!(!after_call.happy);
+    assert!(plan.predicted_state::<TravelState>(3).is_none());
+    assert!(plan.predicted_state::<TestState>(0).is_none());
+    assert_eq!(
+        plan.tasks[2].predicted_changes,
+        vec![FieldChange {
+            field: "cash".to_string(),
+            old: "10".to_string(),
+            new: "9".to_string(),
+        }]
+    );
+}
+
+/// Plans for tasks other than the root task.
+#[test]
+fn test_plan_tasks() {
+    let (htn, atr) = travel::htn();
+    let mut planner = HtnPlanner::new(&htn, &atr);
+    let initial_state = travel::TravelState::at_home(1);
+    let plan = planner.plan_task(&initial_state, "Taxi").unwrap();
+    assert_eq!(plan.task_names(), vec!["CallTaxi", "RideTaxi", "PayTaxi"]);
+    let plan = planner
+        .plan_tasks(&initial_state, &["Walk", "Taxi"])
+        .unwrap();
+    assert_eq!(
+        plan.task_names(),
+        vec!["Walk", "CallTaxi", "RideTaxi", "PayTaxi"]
+    );
+    // walking makes us happy, so we can't walk again
+    assert!(matches!(
+        planner.plan_tasks(&initial_state, &["Walk", "Walk"]),
+        Err(HtnErr::Planning { .. })
+    ));
+    assert!(planner.failed());
+    assert!(matches!(
+        planner.plan_task(&initial_state, "Fly"),
+        Err(HtnErr::UnknownTask { task }) if task == "Fly"
+    ));
+}
+
+/// Enumerates alternative plans, in priority order.
+#[test]
+fn test_plan_alternatives() {
+    use travel::TravelState;
+
+    let (htn, atr) = travel::htn();
+    let mut planner = HtnPlanner::new(&htn, &atr);
+    let initial_state = TravelState::at_home(1);
+    let plans = planner.plan_alternatives(&initial_state, 5, 100);
+    assert_eq!(plans.len(), 2);
+    assert_eq!(plans[0].task_names(), vec!["Walk"]);
+    assert_eq!(plans[0].mtr(), &[0]);
+    assert_eq!(
+        plans[1].task_names(),
+        vec!["CallTaxi", "RideTaxi", "PayTaxi"]
+    );
+    assert_eq!(plans[1].mtr(), &[1, 0]);
+    assert!(plans[0] > plans[1]);
+    // bounded by number of plans
+    assert_eq!(planner.plan_alternatives(&initial_state, 1, 100).len(), 1);
+    // bounded by search effort: finding the taxi plan takes 7 steps
+    assert_eq!(planner.plan_alternatives(&initial_state, 5, 6).len(), 1);
+    assert_eq!(planner.plan_alternatives(&initial_state, 5, 7).len(), 2);
+    // walking isn't an option when it's far
+    let plans = planner.plan_alternatives(&TravelState::at_home(5), 5, 100);
+    assert_eq!(plans.len(), 1);
+    assert_eq!(
+        plans[0].task_names(),
+        vec!["CallTaxi", "RideTaxi", "PayTaxi"]
+    );
+
+    // walking has priority, but a taxi is faster for longer distances
+    let plan = planner.plan_fastest(&initial_state, 5, 100).unwrap();
+    assert_eq!(plan.task_names(), vec!["Walk"]);
+    let far = TravelState::at_home(4);
+    assert_eq!(planner.plan(&far).task_names(), vec!["Walk"]);
+    let plan = planner.plan_fastest(&far, 5, 100).unwrap();
+    assert_eq!(plan.task_names(), vec!["CallTaxi", "RideTaxi", "PayTaxi"]);
+}
+
 #[test]
 fn test_options() {
     {
@@ -538,3 +914,2002 @@ fn test_options() {
     };
     assert!(condition.evaluate(&initial_state, &atr));
 }
+
+#[test]
+fn test_plan_trace() {
+    #[derive(Reflect, Resource, Clone, Debug, Default, Component)]
+    #[reflect(Default, Resource)]
+    struct State {
+        distance: i32,
+        cash: i32,
+        happy: bool,
+    }
+
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+
+    compound_task "TravelToPark" {
+        method "Too poor" {
+            preconditions: [cash < 0]
+            subtasks: [ Walk ]
+        }
+        method "Walk" {
+            subtasks: [ Walk ]
+        }
+        method "Taxi" {
+            subtasks: [ Taxi ]
+        }
+    }
+
+    primitive_task "Walk" {
+        operator: WalkOperator
+        preconditions: [distance <= 4]
+        effects: [happy = true]
+    }
+
+    primitive_task "Taxi" {
+        operator: TaxiOperator
+        preconditions: [cash >= 1]
+        effects: [cash -= 1, happy = true]
+    }
+    "#;
+    let (htn, atr) = planner_htn::<State>(src);
+
+    let state = State {
+        distance: 5,
+        cash: 10,
+        happy: false,
+    };
+
+    // tracing is opt-in
+    let plan = HtnPlanner::new(&htn, &atr).plan(&state);
+    assert!(plan.trace().is_none());
+
+    let mut planner = HtnPlanner::new(&htn, &atr).with_trace();
+    let plan = planner.plan(&state);
+    assert_eq!(plan.task_names(), vec!["Taxi"]);
+    let trace = plan.trace().expect("Trace should be recorded");
+    assert_eq!(
+        trace.steps,
+        vec![
+            TraceStep::VisitTask {
+                task: "TravelToPark".to_string()
+            },
+            TraceStep::TryMethod {
+                task: "TravelToPark".to_string(),
+                method_index: 0,
+                method_name: Some("Too poor".to_string()),
+                failed_condition: Some("cash < 0".to_string()),
+            },
+            TraceStep::TryMethod {
+                task: "TravelToPark".to_string(),
+                method_index: 1,
+                method_name: Some("Walk".to_string()),
+                failed_condition: None,
+            },
+            TraceStep::VisitTask {
+                task: "Walk".to_string()
+            },
+            TraceStep::RejectPrimitive {
+                task: "Walk".to_string(),
+                failed_condition: Some("distance <= 4".to_string()),
+            },
+            TraceStep::Backtrack {
+                task: "TravelToPark".to_string(),
+                skip_methods: 2,
+            },
+            TraceStep::VisitTask {
+                task: "TravelToPark".to_string()
+            },
+            TraceStep::TryMethod {
+                task: "TravelToPark".to_string(),
+                method_index: 2,
+                method_name: Some("Taxi".to_string()),
+                failed_condition: None,
+            },
+            TraceStep::VisitTask {
+                task: "Taxi".to_string()
+            },
+            TraceStep::AddPrimitive {
+                task: "Taxi".to_string(),
+                state_diff: vec![
+                    FieldChange {
+                        field: "cash".to_string(),
+                        old: "10".to_string(),
+                        new: "9".to_string(),
+                    },
+                    FieldChange {
+                        field: "happy".to_string(),
+                        old: "false".to_string(),
+                        new: "true".to_string(),
+                    },
+                ],
+            },
+        ]
+    );
+
+    // planning again with the same planner starts a fresh trace
+    let plan = planner.plan(&State {
+        distance: 1,
+        ..state
+    });
+    assert_eq!(plan.task_names(), vec!["Walk"]);
+    assert_eq!(plan.trace().unwrap().steps.len(), 5);
+}
+
+/// Effects applied by primitives in an abandoned branch must not be visible to the methods
+/// tried after backtracking.
+#[test]
+fn test_backtracking_restores_state() {
+    #[derive(Reflect, Resource, Clone, Debug, Default, Component)]
+    #[reflect(Default, Resource)]
+    struct State {
+        has_key: bool,
+        cash: i32,
+        impossible: bool,
+    }
+
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+
+    compound_task "Root" {
+        method "Pick the lock" {
+            subtasks: [ FindKey, Impossible ]
+        }
+        method "Use the key" {
+            preconditions: [has_key == true]
+            subtasks: [ UnlockDoor ]
+        }
+        method "Buy a key" {
+            subtasks: [ BuyKey, Shop ]
+        }
+    }
+
+    compound_task "Shop" {
+        method "Spend it all" {
+            subtasks: [ Earn, Impossible ]
+        }
+        method "Spend what we have" {
+            subtasks: [ SpendCash ]
+        }
+    }
+
+    primitive_task "FindKey" {
+        operator: DummyOperator
+        effects: [has_key = true]
+    }
+
+    primitive_task "Impossible" {
+        operator: DummyOperator
+        preconditions: [impossible == true]
+    }
+
+    primitive_task "UnlockDoor" {
+        operator: DummyOperator
+        preconditions: [has_key == true]
+    }
+
+    primitive_task "BuyKey" {
+        operator: DummyOperator
+        preconditions: [cash >= 1]
+    }
+
+    primitive_task "Earn" {
+        operator: DummyOperator
+        effects: [cash += 10]
+    }
+
+    primitive_task "SpendCash" {
+        operator: DummyOperator
+        preconditions: [cash < 10]
+        effects: [cash -= 1]
+    }
+    "#;
+    let (htn, atr) = planner_htn::<State>(src);
+
+    let mut planner = HtnPlanner::new(&htn, &atr);
+
+    // FindKey sets has_key before Impossible fails, which would otherwise enable "Use the key".
+    let plan = planner.plan(&State {
+        cash: 1,
+        ..default()
+    });
+    assert_eq!(plan.task_names(), vec!["BuyKey", "SpendCash"]);
+
+    // Earn adds cash before Impossible fails, which would otherwise disable SpendCash.
+    let plan = planner.plan(&State {
+        cash: 5,
+        ..default()
+    });
+    assert_eq!(plan.task_names(), vec!["BuyKey", "SpendCash"]);
+
+    // with no cash, nothing works. "Use the key" must not be chosen.
+    let plan = planner.plan(&State::default());
+    assert!(plan.task_names().is_empty());
+}
+
+/// The undo log must restore non-literal field types too, such as enums and options.
+#[test]
+fn test_backtracking_rolls_back_enum_and_option_fields() {
+    #[derive(Reflect, Resource, Clone, Debug, Default, Component)]
+    #[reflect(Default, Resource)]
+    struct State {
+        location: Location,
+        target: Option<f32>,
+        other_target: Option<f32>,
+        impossible: bool,
+    }
+
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+
+    compound_task "Root" {
+        method {
+            subtasks: [ Travel, Impossible ]
+        }
+        method {
+            preconditions: [location == Location::Home, target != None]
+            subtasks: [ StayHome ]
+        }
+    }
+
+    primitive_task "Travel" {
+        operator: DummyOperator
+        effects: [location = Location::Work, target = None, target = other_target]
+    }
+
+    primitive_task "Impossible" {
+        operator: DummyOperator
+        preconditions: [impossible == true]
+    }
+
+    primitive_task "StayHome" {
+        operator: DummyOperator
+    }
+    "#;
+    let (htn, atr) = planner_htn::<State>(src);
+
+    let mut planner = HtnPlanner::new(&htn, &atr);
+    let plan = planner.plan(&State {
+        target: Some(1.0),
+        ..default()
+    });
+    assert_eq!(plan.task_names(), vec!["StayHome"]);
+}
+
+/// Subtasks and method choices of an abandoned method must not survive backtracking.
+#[test]
+fn test_backtracking_restores_task_stack_and_mtr() {
+    #[derive(Reflect, Resource, Clone, Debug, Default, Component)]
+    #[reflect(Default, Resource)]
+    struct State {
+        impossible: bool,
+    }
+
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+
+    compound_task "Root" {
+        method {
+            subtasks: [ Nested, Impossible, Leftover ]
+        }
+        method {
+            subtasks: [ Nested ]
+        }
+    }
+
+    compound_task "Nested" {
+        method {
+            preconditions: [impossible == true]
+            subtasks: [ Leftover ]
+        }
+        method {
+            subtasks: [ Fine ]
+        }
+    }
+
+    primitive_task "Impossible" {
+        operator: DummyOperator
+        preconditions: [impossible == true]
+    }
+
+    primitive_task "Leftover" {
+        operator: DummyOperator
+    }
+
+    primitive_task "Fine" {
+        operator: DummyOperator
+    }
+    "#;
+    let (htn, atr) = planner_htn::<State>(src);
+
+    let mut planner = HtnPlanner::new(&htn, &atr);
+    let plan = planner.plan(&State::default());
+    assert_eq!(plan.task_names(), vec!["Fine"]);
+    assert_eq!(plan.mtr(), &[1, 1]);
+}
+
+#[test]
+fn test_max_steps() {
+    // each level of recursion takes a couple of steps, deeper than the old limit of 100 steps
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    compound_task "Count" {
+        method {
+            preconditions: [counter < 80]
+            subtasks: [Increment, Count]
+        }
+        method {
+            subtasks: [Done]
+        }
+    }
+    primitive_task "Increment" {
+        operator: TestOperator1
+        effects: [counter += 1]
+    }
+    primitive_task "Done" {
+        operator: TestOperator1
+    }
+    "#;
+    let app = setup_app();
+    let htn = parse_htn::<TestState>(src).expect("Failed to parse htn");
+    let mut planner = HtnPlanner::new(&htn, app.atr());
+    let plan = planner.plan(&TestState::default());
+    assert!(!planner.failed());
+    assert_eq!(plan.tasks.len(), 81);
+
+    let mut planner = HtnPlanner::new(&htn, app.atr()).with_max_steps(100);
+    planner.plan(&TestState::default());
+    assert!(planner.failed());
+}
+
+// the state types are shared with the examples
+#[allow(dead_code)]
+#[path = "../examples/miner/state.rs"]
+mod miner;
+#[allow(dead_code)]
+#[path = "../examples/troll/state.rs"]
+mod troll;
+
+/// Plans against the domains used by the examples.
+#[test]
+fn test_example_domains() {
+    // both domains have a `Location` enum, so they need separate registries.
+    let atr = AppTypeRegistry::default();
+    {
+        let mut atr = atr.write();
+        atr.register::<miner::GameState>();
+        atr.register::<miner::Location>();
+    }
+    let compiled = parse_htn::<miner::GameState>(include_str!("../assets/miner.htn"))
+        .expect("Failed to parse miner.htn");
+    assert!(compiled
+        .verify_without_operators(&miner::GameState::default(), &atr)
+        .is_ok());
+    let mut htn = compiled.clone();
+    htn.decompile();
+    let mut planner = HtnPlanner::new(&htn, &atr);
+    let plan = planner.plan(&miner::GameState {
+        energy: 100,
+        location: miner::Location::Outside,
+        ..default()
+    });
+    // earns one gold per loop, until it has 3.
+    let earn_one_gold = [
+        "GoToOre",
+        "MineOre",
+        "GoToSmelter",
+        "SmeltOre",
+        "GoToOutside",
+        "GoToMerchant",
+        "SellMetal",
+        "GoToOutside",
+    ];
+    assert_eq!(plan.task_names(), earn_one_gold.repeat(3));
+
+    // compiled conditions and effects should produce the same plan
+    assert!(compiled.is_compiled());
+    assert!(compiled.tasks.iter().all(|t| match t {
+        Task::Primitive(p) => p.is_compiled(),
+        Task::Compound(c) => c.methods.iter().all(|m| m.is_compiled()),
+    }));
+    let compiled_plan = HtnPlanner::new(&compiled, &atr).plan(&miner::GameState {
+        energy: 100,
+        location: miner::Location::Outside,
+        ..default()
+    });
+    assert_eq!(compiled_plan, plan);
+    assert_eq!(compiled_plan.mtr(), plan.mtr());
+
+    // planning a few steps at a time, resuming from the saved progress each time, should
+    // produce the same plan as planning in one go.
+    let mut planner = HtnPlanner::new(&compiled, &atr);
+    planner.start(&miner::GameState {
+        energy: 100,
+        location: miner::Location::Outside,
+        ..default()
+    });
+    let mut progress = planner.into_progress();
+    let mut slices = 0;
+    let sliced_plan = loop {
+        slices += 1;
+        let mut planner = HtnPlanner::resume(&compiled, &atr, progress);
+        if let Some(plan) = planner.plan_steps(3) {
+            break plan;
+        }
+        assert!(planner.is_planning());
+        progress = planner.into_progress();
+        assert!(progress.steps() <= slices * 3);
+    };
+    assert!(slices > 1);
+    assert_eq!(sliced_plan, plan);
+    assert_eq!(sliced_plan.mtr(), plan.mtr());
+
+    let atr = AppTypeRegistry::default();
+    {
+        let mut atr = atr.write();
+        atr.register::<troll::GameState>();
+        atr.register::<troll::Location>();
+    }
+    let compiled = parse_htn::<troll::GameState>(include_str!("../assets/troll.htn"))
+        .expect("Failed to parse troll.htn");
+    assert!(compiled
+        .verify_without_operators(&troll::GameState::default(), &atr)
+        .is_ok());
+    let mut htn = compiled.clone();
+    htn.decompile();
+    for htn in [&htn, &compiled] {
+        let mut planner = HtnPlanner::new(htn, &atr);
+        let state = troll::GameState {
+            trunk_health: 3,
+            next_bridge_to_check: 1,
+            can_navigate_to_enemy: true,
+            ..default()
+        };
+        let plan = planner.plan(&state);
+        assert_eq!(
+            plan.task_names(),
+            vec!["ChooseBridgeToCheck", "NavigateToBridge", "CheckBridge"]
+        );
+        let plan = planner.plan(&troll::GameState {
+            can_see_enemy: true,
+            ..state.clone()
+        });
+        assert_eq!(plan.task_names(), vec!["NavigateToEnemy", "DoTrunkSlam"]);
+        let plan = planner.plan(&troll::GameState {
+            can_see_enemy: true,
+            trunk_health: 0,
+            ..state.clone()
+        });
+        assert_eq!(
+            plan.task_names(),
+            vec![
+                "FindTrunk",
+                "NavigateToTrunk",
+                "UprootTrunk",
+                "NavigateToEnemy",
+                "DoTrunkSlam"
+            ]
+        );
+        // expects to regain line of sight by navigating to where the enemy was last seen
+        let plan = planner.plan(&troll::GameState {
+            has_seen_enemy_recently: true,
+            ..state.clone()
+        });
+        assert_eq!(
+            plan.task_names(),
+            vec!["NavigateToLastEnemyLoc", "RegainLOSRoar", "CatchBreath"]
+        );
+    }
+}
+
+#[derive(Resource)]
+struct ToggleHtn(Handle<HtnAsset<TestState>>);
+
+/// An app with a domain that turns `tog` on if it's off, otherwise turns it off.
+fn toggle_app() -> App {
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    compound_task "Toggle" {
+        method {
+            preconditions: [tog == false]
+            subtasks: [TurnOn]
+        }
+        method {
+            subtasks: [TurnOff]
+        }
+    }
+    primitive_task "TurnOn" {
+        operator: TestOperator1
+        preconditions: [tog == false]
+        effects: [tog = true]
+    }
+    primitive_task "TurnOff" {
+        operator: TestOperator1
+        preconditions: [tog == true]
+        effects: [tog = false]
+    }
+    "#;
+    let mut app = htn_app::<TestState>();
+    let htn = parse_htn::<TestState>(src).expect("Failed to parse htn");
+    let htn_handle = add_htn(&mut app, htn);
+    app.insert_resource(ToggleHtn(htn_handle));
+    app
+}
+
+fn spawn_toggle_supervisor(app: &mut App) -> Entity {
+    let htn_handle = app.world().resource::<ToggleHtn>().0.clone();
+    spawn_supervisor(app, &htn_handle, &TestState::default())
+}
+
+#[test]
+fn test_async_planning() {
+    let mut app = toggle_app();
+    let sup = spawn_toggle_supervisor(&mut app);
+    app.world_mut().entity_mut(sup).insert(AsyncPlanning);
+
+    replan(&mut app, sup);
+    assert!(app.world().get::<PlanningInProgress>(sup).is_some());
+    assert!(app.world().get::<Plan>(sup).is_none());
+    // requesting again while planning just flags that another replan is needed
+    app.world_mut().trigger_targets(ReplanRequest, sup);
+    assert!(app
+        .world()
+        .get::<PlanningInProgress>(sup)
+        .unwrap()
+        .replan_requested());
+
+    // the state changes before the plan is ready, invalidating it
+    app.world_mut().get_mut::<TestState>(sup).unwrap().tog = true;
+
+    for _ in 0..1000 {
+        app.update();
+        if app.world().get::<Plan>(sup).is_some() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+    let plan = app.world().get::<Plan>(sup).expect("Plan should be ready");
+    assert_eq!(plan.task_names(), vec!["TurnOff"]);
+}
+
+#[test]
+fn test_time_sliced_planning() {
+    let mut app = toggle_app();
+    let sup = spawn_toggle_supervisor(&mut app);
+    app.world_mut()
+        .entity_mut(sup)
+        .insert(TimeSlicedPlanning { steps_per_frame: 1 });
+
+    replan(&mut app, sup);
+    assert!(app.world().get::<PlanningInProgress>(sup).is_some());
+
+    // one step per frame: visiting Toggle, then TurnOn
+    app.update();
+    assert!(app.world().get::<Plan>(sup).is_none());
+    assert!(app.world().get::<PlanningInProgress>(sup).is_some());
+    app.update();
+    assert!(app.world().get::<PlanningInProgress>(sup).is_none());
+    let plan = app.world().get::<Plan>(sup).expect("Plan should be ready");
+    assert_eq!(plan.task_names(), vec!["TurnOn"]);
+}
+
+#[test]
+fn test_replan_scheduler() {
+    let mut app = toggle_app();
+    app.insert_resource(ReplanScheduler::default().with_max_plans_per_frame(1));
+    let low = spawn_toggle_supervisor(&mut app);
+    let high = spawn_toggle_supervisor(&mut app);
+    let mid = spawn_toggle_supervisor(&mut app);
+    app.world_mut()
+        .entity_mut(high)
+        .insert(ReplanPriority(10.0));
+    app.world_mut().entity_mut(mid).insert(ReplanPriority(5.0));
+
+    for sup in [low, high, mid, high, low] {
+        app.world_mut().trigger_targets(ReplanRequest, sup);
+    }
+    // repeated requests are coalesced
+    assert_eq!(app.world().resource::<ReplanScheduler>().len(), 3);
+    assert!(app.world().get::<Plan>(high).is_none());
+
+    // one plan per frame, highest priority first
+    for expected in [high, mid, low] {
+        app.update();
+        assert!(app.world().get::<Plan>(expected).is_some());
+        assert!(!app
+            .world()
+            .resource::<ReplanScheduler>()
+            .is_queued(expected));
+    }
+    assert!(app.world().resource::<ReplanScheduler>().is_empty());
+}
+
+#[test]
+fn test_supervisor_planner() {
+    let mut app = toggle_app();
+    let sup = spawn_toggle_supervisor(&mut app);
+    app.world_mut().get_mut::<TestState>(sup).unwrap().tog = true;
+    let plans = app
+        .world_mut()
+        .run_system_once(move |planner: HtnSupervisorPlanner<TestState>| {
+            (
+                planner.plan_task(sup, "TurnOff"),
+                planner.plan_task(sup, "TurnOn"),
+            )
+        })
+        .unwrap();
+    assert_eq!(plans.0.unwrap().task_names(), vec!["TurnOff"]);
+    assert!(plans.1.is_err());
+    // planning doesn't execute the plan
+    assert!(app.world().get::<Plan>(sup).is_none());
+}
+
+#[test]
+fn test_agenda() {
+    #[derive(Resource, Default)]
+    struct AgendaEvents(Vec<HtnAgendaEvent>);
+
+    let mut app = toggle_app();
+    app.init_resource::<AgendaEvents>();
+    app.add_observer(
+        |t: Trigger<HtnAgendaEvent>, mut events: ResMut<AgendaEvents>| {
+            events.0.push(t.event().clone());
+        },
+    );
+    let sup = spawn_toggle_supervisor(&mut app);
+
+    // tog is false, so we can't turn it off, but we can turn it on
+    app.world_mut()
+        .commands()
+        .entity(sup)
+        .replace_htn_agenda(["TurnOff", "TurnOn"]);
+    app.world_mut().flush();
+    assert_eq!(
+        app.world().resource::<AgendaEvents>().0,
+        vec![HtnAgendaEvent::Failed("TurnOff".to_string())]
+    );
+    let plan = app.world().get::<Plan>(sup).unwrap();
+    assert_eq!(plan.agenda_item(), Some("TurnOn"));
+    assert_eq!(plan.task_names(), vec!["TurnOn"]);
+    let agenda = app.world().get::<HtnAgenda>(sup).unwrap();
+    assert_eq!(agenda.iter().collect::<Vec<_>>(), vec!["TurnOn"]);
+
+    // completing the plan completes the agenda item, and we go back to planning for the root task
+    let task_id = plan.tasks[0].id.clone();
+    app.world_mut()
+        .trigger_targets(TaskComplete::new(task_id, true), sup);
+    app.world_mut().flush();
+    assert_eq!(
+        app.world().resource::<AgendaEvents>().0,
+        vec![
+            HtnAgendaEvent::Failed("TurnOff".to_string()),
+            HtnAgendaEvent::Completed("TurnOn".to_string())
+        ]
+    );
+    assert!(app.world().get::<HtnAgenda>(sup).unwrap().is_empty());
+    let plan = app.world().get::<Plan>(sup).unwrap();
+    assert_eq!(plan.agenda_item(), None);
+    assert_eq!(plan.task_names(), vec!["TurnOff"]);
+
+    // tog is true now, so TurnOn fails straight away
+    app.world_mut()
+        .commands()
+        .entity(sup)
+        .push_htn_task("TurnOn");
+    app.world_mut().flush();
+    assert_eq!(
+        app.world().resource::<AgendaEvents>().0.last(),
+        Some(&HtnAgendaEvent::Failed("TurnOn".to_string()))
+    );
+    // once it's turned off, pushing TurnOn replaces the root task plan
+    app.world_mut().get_mut::<TestState>(sup).unwrap().tog = false;
+    app.world_mut()
+        .commands()
+        .entity(sup)
+        .push_htn_task("TurnOn");
+    app.world_mut().flush();
+    let plan = app.world().get::<Plan>(sup).unwrap();
+    assert_eq!(plan.agenda_item(), Some("TurnOn"));
+
+    // clearing the agenda goes back to the root task
+    app.world_mut().commands().entity(sup).clear_htn_agenda();
+    app.world_mut().flush();
+    let plan = app.world().get::<Plan>(sup).unwrap();
+    assert_eq!(plan.agenda_item(), None);
+    assert_eq!(plan.task_names(), vec!["TurnOn"]);
+}
+
+#[test]
+fn test_world_state() {
+    #[derive(Reflect, Default, Clone, Debug, PartialEq)]
+    #[reflect(Default)]
+    struct Shared {
+        coins: i32,
+    }
+
+    #[derive(Reflect, Component, Default, Clone, Debug)]
+    #[reflect(Default, Component)]
+    struct Agent {
+        has_key: bool,
+        carrying: i32,
+        world: Shared,
+    }
+
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    compound_task "Gather" {
+        method {
+            preconditions: [world.coins > 0]
+            subtasks: [TakeCoin, UnlockChest]
+        }
+        method {
+            preconditions: [world.coins > 0]
+            subtasks: [TakeCoin]
+        }
+        method {
+            subtasks: [Idle]
+        }
+    }
+    primitive_task "TakeCoin" {
+        operator: TestOperator1
+        preconditions: [world.coins > 0]
+        effects: [world.coins -= 1, carrying += 1]
+    }
+    primitive_task "UnlockChest" {
+        operator: TestOperator1
+        preconditions: [has_key == true]
+    }
+    primitive_task "Idle" {
+        operator: TestOperator1
+    }
+    "#;
+    let mut app = htn_app::<Agent>();
+    app.add_plugins(HtnWorldStatePlugin::<Agent, Shared>::default());
+    let htn = parse_htn::<Agent>(src).expect("Failed to parse htn");
+    htn.verify_without_operators(&Agent::default(), app.atr())
+        .expect("Failed to verify htn");
+
+    // backtracking out of the first method must undo its effect on the world
+    let mut reflective = htn.clone();
+    reflective.decompile();
+    let mut planner = HtnPlanner::new(&reflective, app.atr());
+    let state = Agent {
+        world: Shared { coins: 1 },
+        ..default()
+    };
+    assert_eq!(planner.plan(&state).task_names(), vec!["TakeCoin"]);
+    assert_eq!(planner.plan(&Agent::default()).task_names(), vec!["Idle"]);
+
+    // world fields are compiled too, and backtracking undoes compiled effects on them
+    let compiled = htn.clone();
+    assert!(compiled.is_compiled());
+    assert!(compiled.tasks.iter().all(|t| match t {
+        Task::Primitive(p) => p.is_compiled(),
+        Task::Compound(c) => c.methods.iter().all(|m| m.is_compiled()),
+    }));
+    let mut planner = HtnPlanner::new(&compiled, app.atr());
+    assert_eq!(planner.plan(&state).task_names(), vec!["TakeCoin"]);
+    assert_eq!(planner.plan(&Agent::default()).task_names(), vec!["Idle"]);
+
+    let htn_handle = add_htn(&mut app, htn);
+    app.insert_resource(HtnWorldState(Shared { coins: 1 }));
+    let sup_a = spawn_supervisor(&mut app, &htn_handle, &Agent::default());
+    let sup_b = spawn_supervisor(&mut app, &htn_handle, &Agent::default());
+    // the world state is copied to new supervisors
+    app.update();
+    for sup in [sup_a, sup_b] {
+        assert_eq!(app.world().get::<Agent>(sup).unwrap().world.coins, 1);
+        replan(&mut app, sup);
+        let plan = app.world().get::<Plan>(sup).unwrap();
+        assert_eq!(plan.task_names(), vec!["TakeCoin"]);
+    }
+
+    // a takes the coin, which is committed to the world state
+    complete_first_task(&mut app, sup_a, true);
+    assert_eq!(app.world().resource::<HtnWorldState<Shared>>().coins, 0);
+    assert_eq!(app.world().get::<Agent>(sup_a).unwrap().carrying, 1);
+    assert_eq!(
+        app.world().get::<Plan>(sup_a).unwrap().task_names(),
+        vec!["Idle"]
+    );
+
+    // b sees the coin has gone, and its plan is no longer valid
+    app.update();
+    assert_eq!(app.world().get::<Agent>(sup_b).unwrap().world.coins, 0);
+    assert_eq!(
+        app.world().get::<Plan>(sup_b).unwrap().task_names(),
+        vec!["Idle"]
+    );
+}
+
+#[test]
+fn test_claims() {
+    #[derive(Reflect, Component, Default, Clone, Debug)]
+    #[reflect(Default, Component)]
+    struct Bot {
+        coin: Option<Vec2>,
+    }
+
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    compound_task "Collect" {
+        method {
+            preconditions: [coin != None, unclaimed(coin)]
+            subtasks: [ClaimCoin, MoveToCoin]
+        }
+        method {
+            subtasks: [Wander]
+        }
+    }
+    primitive_task "ClaimCoin" {
+        operator: TestOperator1
+        preconditions: [unclaimed(coin)]
+        effects: [claim(coin)]
+    }
+    primitive_task "MoveToCoin" {
+        operator: TestOperator1
+    }
+    primitive_task "Wander" {
+        operator: TestOperator1
+    }
+    "#;
+    let mut app = htn_app::<Bot>();
+    let htn = parse_htn::<Bot>(src).expect("Failed to parse htn");
+    htn.verify_without_operators(&Bot::default(), app.atr())
+        .expect("Failed to verify htn");
+    let state = Bot {
+        coin: Some(Vec2::new(1.0, 2.0)),
+    };
+    let key = ClaimKey::from_field(&state, "coin").unwrap();
+
+    let plan = HtnPlanner::new(&htn, app.atr()).plan(&state);
+    assert_eq!(plan.task_names(), vec!["ClaimCoin", "MoveToCoin"]);
+    assert_eq!(plan.claims().to_vec(), vec![key.clone()]);
+    let plan = HtnPlanner::new(&htn, app.atr())
+        .with_claimed([key.clone()].into_iter().collect())
+        .plan(&state);
+    assert_eq!(plan.task_names(), vec!["Wander"]);
+    assert!(plan.claims().is_empty());
+    // plans made without knowing about claims, to insert later
+    let unaware_plan = HtnPlanner::new(&htn, app.atr()).plan(&state);
+    let another_unaware_plan = HtnPlanner::new(&htn, app.atr()).plan(&state);
+
+    // keys match equal values, whatever their debug output, and only values of the same type
+    let key_of = |value: &dyn PartialReflect| ClaimKey::new(value).unwrap();
+    assert_eq!(key_of(&0.0f32), key_of(&-0.0f32));
+    assert_ne!(key_of(&1.0f32), key_of(&1.0f64));
+    assert_eq!(key_of(&Some(Vec2::new(1.0, 2.0))), key);
+    assert!(bevy::utils::HashSet::<ClaimKey>::from_iter([key.clone()])
+        .contains(&key_of(&Some(Vec2::new(1.0, 2.0)))));
+    // values that can't be hashed can't be claimed
+    assert!(ClaimKey::new(&bevy::utils::HashMap::<u32, u32>::default()).is_none());
+    #[derive(Reflect, Component, Default, Clone, Debug)]
+    #[reflect(Default, Component)]
+    struct Hoarder {
+        stash: bevy::utils::HashMap<u32, u32>,
+    }
+    app.register_type::<Hoarder>();
+    let hoard = src.replace("coin", "stash").replace("stash != None, ", "");
+    let hoard = parse_htn::<Hoarder>(&hoard).expect("Failed to parse htn");
+    assert!(matches!(
+        hoard.verify_without_operators(&Hoarder::default(), app.atr()),
+        Err(HtnErr::Condition { .. } | HtnErr::Effect { .. })
+    ));
+
+    let htn_handle = add_htn(&mut app, htn);
+    let sup_a = spawn_supervisor(&mut app, &htn_handle, &state);
+    let sup_b = spawn_supervisor(&mut app, &htn_handle, &state);
+    let task_names = |app: &App, sup: Entity| app.world().get::<Plan>(sup).unwrap().task_names();
+
+    // the supervisors being added would otherwise cause a replan during the next update
+    app.update();
+
+    // a claims the coin, so b wanders
+    for sup in [sup_a, sup_b] {
+        replan(&mut app, sup);
+    }
+    assert_eq!(task_names(&app, sup_a), vec!["ClaimCoin", "MoveToCoin"]);
+    assert_eq!(task_names(&app, sup_b), vec!["Wander"]);
+    assert_eq!(app.world().resource::<HtnClaims>().owner(&key), Some(sup_a));
+
+    // a plan claiming the coin is aborted if it's inserted while a holds the claim
+    app.world_mut().entity_mut(sup_b).insert(unaware_plan);
+    app.world_mut().flush();
+    assert_eq!(task_names(&app, sup_b), vec!["Wander"]);
+    assert_eq!(app.world().resource::<HtnClaims>().owner(&key), Some(sup_a));
+    // unless b's replan policy doesn't replan invalidated plans
+    app.world_mut()
+        .entity_mut(sup_b)
+        .insert(ReplanPolicy::on_finish());
+    app.world_mut()
+        .entity_mut(sup_b)
+        .insert(another_unaware_plan);
+    app.world_mut().flush();
+    assert_eq!(task_names(&app, sup_b), vec!["ClaimCoin", "MoveToCoin"]);
+    assert_eq!(
+        app.world().get::<Plan>(sup_b).unwrap().status(),
+        PlanStatus::Aborted
+    );
+    app.world_mut().entity_mut(sup_b).remove::<ReplanPolicy>();
+
+    // aborting a's plan releases the claim
+    app.world_mut().get_mut::<Plan>(sup_a).unwrap().abort();
+    app.update();
+    assert!(app.world().resource::<HtnClaims>().is_empty());
+
+    // as does despawning a
+    replan(&mut app, sup_a);
+    assert_eq!(app.world().resource::<HtnClaims>().owner(&key), Some(sup_a));
+    app.world_mut().entity_mut(sup_a).despawn();
+    assert!(app.world().resource::<HtnClaims>().is_empty());
+    replan(&mut app, sup_b);
+    assert_eq!(task_names(&app, sup_b), vec!["ClaimCoin", "MoveToCoin"]);
+    assert_eq!(app.world().resource::<HtnClaims>().owner(&key), Some(sup_b));
+}
+
+#[test]
+fn test_plan_ids() {
+    let mut app = toggle_app();
+    let sup_a = spawn_toggle_supervisor(&mut app);
+    let sup_b = spawn_toggle_supervisor(&mut app);
+    let plan_id = |app: &App, sup: Entity| app.world().get::<Plan>(sup).unwrap().id();
+
+    // ids count up per supervisor, and tasks are given the id of their plan
+    replan(&mut app, sup_a);
+    assert_eq!(plan_id(&app, sup_a), 1);
+    let task_id = app.world().get::<Plan>(sup_a).unwrap().tasks[0].id.clone();
+    assert_eq!(task_id.plan_id(), 1);
+    app.world_mut()
+        .trigger_targets(TaskComplete::new(task_id, true), sup_a);
+    app.world_mut().flush();
+    assert_eq!(plan_id(&app, sup_a), 2);
+    assert_eq!(app.world().get::<PlanIdCounter>(sup_a).unwrap().last(), 2);
+    replan(&mut app, sup_b);
+    assert_eq!(plan_id(&app, sup_b), 1);
+}
+
+#[test]
+fn test_seeded_htn_assets() {
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    primitive_task "TurnOn" {
+        operator: TestOperator1
+        preconditions: [tog == false]
+        effects: [tog = true]
+    }
+    "#;
+    // loads the HTN the way the asset server does, so it's seeded and compiled once loaded
+    let load = |seed: u64| {
+        let mut app = htn_app::<TestState>();
+        app.insert_resource(HtnRng::seeded(seed));
+        let htn = parse_htn::<TestState>(src).expect("Failed to parse htn");
+        let handle = app.world().resource::<AssetServer>().add_async(async move {
+            Ok::<_, HtnAssetError>(HtnAsset {
+                htn: std::sync::Arc::new(htn),
+                seed: 0,
+            })
+        });
+        for _ in 0..100 {
+            app.update();
+            let assets = app.world().resource::<Assets<HtnAsset<TestState>>>();
+            if let Some(asset) = assets.get(&handle).filter(|asset| asset.seed != 0) {
+                assert!(asset.htn.is_compiled());
+                return asset.seed;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        panic!("HtnAsset wasn't loaded");
+    };
+    // the same seed gives the same asset seeds, so runs can be reproduced
+    assert_eq!(load(42), load(42));
+    assert_ne!(load(42), load(43));
+}
+
+#[test]
+fn test_plan_lifecycle() {
+    #[derive(Resource, Default)]
+    struct PlanEvents(Vec<(u32, PlanStatus)>);
+
+    let mut app = toggle_app();
+    app.init_resource::<PlanEvents>();
+    app.add_observer(|t: Trigger<HtnPlanEvent>, mut events: ResMut<PlanEvents>| {
+        events.0.push((t.event().plan_id, t.event().status));
+    });
+    let sup = spawn_toggle_supervisor(&mut app);
+    let status = |app: &App| app.world().get::<Plan>(sup).unwrap().status();
+
+    // the plan starts running as soon as it's inserted, events are reported at the end of the frame
+    app.update();
+    replan(&mut app, sup);
+    assert_eq!(status(&app), PlanStatus::Running);
+    assert!(app.world().resource::<PlanEvents>().0.is_empty());
+    app.update();
+    assert_eq!(status(&app), PlanStatus::Running);
+    assert_eq!(
+        app.world().resource::<PlanEvents>().0,
+        vec![(1, PlanStatus::Pending), (1, PlanStatus::Running)]
+    );
+
+    // a succeeded plan is replaced by the next one, and kept in the history
+    complete_first_task(&mut app, sup, true);
+    let plan = app.world().get::<Plan>(sup).unwrap();
+    assert_eq!((plan.id(), plan.status()), (2, PlanStatus::Running));
+    let history = app.world().get::<PlanHistory>(sup).unwrap();
+    assert_eq!(
+        history.last(),
+        Some(&PlanRecord {
+            plan_id: 1,
+            tasks: vec!["TurnOn".to_string()],
+            agenda_item: None,
+            status: PlanStatus::Succeeded,
+        })
+    );
+
+    // a running plan replaced by a new one is aborted
+    replan(&mut app, sup);
+    assert_eq!(app.world().get::<Plan>(sup).unwrap().id(), 2);
+    let htn_handle = app.world().resource::<ToggleHtn>().0.clone();
+    let htn = app
+        .world()
+        .resource::<Assets<HtnAsset<TestState>>>()
+        .get(&htn_handle)
+        .unwrap()
+        .htn
+        .clone();
+    let state = app.world().get::<TestState>(sup).unwrap().clone();
+    let new_plan = HtnPlanner::new(&htn, app.atr()).plan(&state);
+    app.world_mut().entity_mut(sup).insert(new_plan);
+    app.update();
+    assert_eq!(
+        app.world()
+            .get::<PlanHistory>(sup)
+            .unwrap()
+            .last()
+            .map(|r| (r.plan_id, r.status)),
+        Some((2, PlanStatus::Aborted))
+    );
+    assert_eq!(
+        app.world().resource::<PlanEvents>().0[2..],
+        [
+            (1, PlanStatus::Succeeded),
+            (2, PlanStatus::Pending),
+            (2, PlanStatus::Running),
+            (2, PlanStatus::Aborted),
+            (3, PlanStatus::Pending),
+            (3, PlanStatus::Running),
+        ]
+    );
+
+    // finished plans don't block new plans, even identical ones
+    app.world_mut().get_mut::<Plan>(sup).unwrap().abort();
+    replan(&mut app, sup);
+    assert_eq!(app.world().get::<Plan>(sup).unwrap().id(), 4);
+    assert_eq!(
+        app.world().get::<PlanHistory>(sup).unwrap().records().len(),
+        3
+    );
+}
+
+#[test]
+fn test_plan_tree() {
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    compound_task "Main" {
+        method "Shortcut" {
+            subtasks: [Setup, Cheat]
+        }
+        method "Properly" {
+            subtasks: [Setup, Work]
+        }
+    }
+    compound_task "Setup" {
+        method {
+            preconditions: [tog == true]
+            subtasks: [Work]
+        }
+        method "Switch on" {
+            subtasks: [TurnOn, Check]
+        }
+    }
+    primitive_task "TurnOn" {
+        operator: TestOperator1
+        effects: [tog = true]
+    }
+    primitive_task "Check" {
+        operator: TestOperator1
+        preconditions: [tog == true]
+    }
+    primitive_task "Cheat" {
+        operator: TestOperator1
+        preconditions: [counter > 5]
+    }
+    primitive_task "Work" {
+        operator: TestOperator1
+        effects: [counter += 1]
+    }
+    "#;
+    let app = setup_app();
+    let htn = parse_htn::<TestState>(src).expect("Failed to parse htn");
+    let plan = HtnPlanner::new(&htn, app.atr()).plan(&TestState::default());
+    assert_eq!(plan.task_names(), vec!["TurnOn", "Check", "Work"]);
+
+    // the failed "Shortcut" decomposition isn't in the tree
+    let tree = plan.tree();
+    let names = tree
+        .nodes()
+        .iter()
+        .map(|n| n.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["Main", "Setup", "TurnOn", "Check", "Work"]);
+    assert_eq!(tree.roots(), &[0]);
+    assert_eq!(tree.node(0).unwrap().children, vec![1, 4]);
+    assert_eq!(tree.node(1).unwrap().children, vec![2, 3]);
+    assert_eq!(
+        tree.node(1).unwrap().kind,
+        PlanNodeKind::Compound {
+            method_index: 1,
+            method_name: Some("Switch on".to_string()),
+        }
+    );
+    assert_eq!(
+        tree.node(4).unwrap().kind,
+        PlanNodeKind::Primitive { task_index: 2 }
+    );
+    assert_eq!(
+        tree.parent_compound(1).map(|n| n.name.as_str()),
+        Some("Setup")
+    );
+    assert_eq!(
+        tree.parent_compound(2).map(|n| n.name.as_str()),
+        Some("Main")
+    );
+    let ancestors = tree
+        .ancestors(3)
+        .map(|n| n.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(ancestors, vec!["Setup", "Main"]);
+    assert_eq!(tree.task_range(0), 0..3);
+    assert_eq!(tree.task_range(1), 0..2);
+    assert_eq!(tree.task_range(4), 2..3);
+    assert_eq!(
+        tree.to_string(),
+        "Main (Properly)\n  Setup (Switch on)\n    TurnOn\n    Check\n  Work\n"
+    );
+
+    // planning for several tasks gives a tree with several roots
+    let plan = HtnPlanner::new(&htn, app.atr())
+        .plan_tasks(&TestState::default(), &["Setup", "Work"])
+        .unwrap();
+    assert_eq!(plan.tree().roots(), &[0, 3]);
+    assert!(plan.tree().parent_compound(2).is_none());
+}
+
+#[test]
+fn test_plan_repair() {
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    compound_task "Main" {
+        method {
+            subtasks: [Travel, Arrive]
+        }
+    }
+    compound_task "Travel" {
+        method "Quickly" {
+            subtasks: [Sprint]
+        }
+        method "Slowly" {
+            subtasks: [Walk]
+        }
+    }
+    primitive_task "Sprint" {
+        operator: TestOperator1
+    }
+    primitive_task "Walk" {
+        operator: TestOperator1
+        effects: [counter += 1]
+    }
+    primitive_task "Arrive" {
+        operator: TestOperator1
+    }
+    "#;
+    let mut app = htn_app::<TestState>();
+    let htn = parse_htn::<TestState>(src).expect("Failed to parse htn");
+    let state = TestState::default();
+
+    // the failed task's compound tries its next method, followed by the rest of the plan
+    let plan = HtnPlanner::new(&htn, app.atr()).plan(&state);
+    assert_eq!(plan.task_names(), vec!["Sprint", "Arrive"]);
+    let repaired = HtnPlanner::new(&htn, app.atr())
+        .repair(&plan, 0, &state)
+        .unwrap();
+    assert_eq!(repaired.task_names(), vec!["Walk", "Arrive"]);
+    // ranked by the method it repaired with
+    assert_eq!(plan.mtr(), &[0, 0]);
+    assert_eq!(repaired.mtr(), &[0, 1]);
+    assert!(plan > repaired);
+    assert_eq!(repaired.predicted_state::<TestState>(0).unwrap().counter, 1);
+    // there's no method after "Slowly", and "Main" only has the one
+    assert!(HtnPlanner::new(&htn, app.atr())
+        .repair(&repaired, 0, &state)
+        .is_err());
+    assert!(HtnPlanner::new(&htn, app.atr())
+        .repair(&plan, 1, &state)
+        .is_err());
+
+    let htn_handle = add_htn(&mut app, htn);
+    let sup = spawn_supervisor(&mut app, &htn_handle, &state);
+    app.world_mut().entity_mut(sup).insert(PlanRepair);
+    app.update();
+    replan(&mut app, sup);
+    let task_names = |app: &App| app.world().get::<Plan>(sup).unwrap().task_names();
+
+    // sprinting fails, so we walk instead of replanning from the root
+    complete_first_task(&mut app, sup, false);
+    assert_eq!(task_names(&app), vec!["Walk", "Arrive"]);
+    assert_eq!(
+        app.world().get::<Plan>(sup).unwrap().status(),
+        PlanStatus::Running
+    );
+    assert_eq!(
+        app.world()
+            .get::<PlanHistory>(sup)
+            .unwrap()
+            .last()
+            .map(|r| r.status),
+        Some(PlanStatus::Failed)
+    );
+    // walking fails too, so there's nothing left to repair with and we replan
+    complete_first_task(&mut app, sup, false);
+    assert_eq!(task_names(&app), vec!["Sprint", "Arrive"]);
+}
+
+#[test]
+fn test_plan_repair_priority() {
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    compound_task "Main" {
+        method "Risky" {
+            preconditions: [tog == false]
+            subtasks: [Flaky]
+        }
+        method "Better" {
+            preconditions: [tog == true]
+            subtasks: [Work]
+        }
+        method "Fallback" {
+            subtasks: [Idle]
+        }
+    }
+    primitive_task "Flaky" {
+        operator: TestOperator1
+    }
+    primitive_task "Work" {
+        operator: TestOperator1
+    }
+    primitive_task "Idle" {
+        operator: TestOperator1
+    }
+    "#;
+    let mut app = htn_app::<TestState>();
+    let htn = parse_htn::<TestState>(src).expect("Failed to parse htn");
+    let htn_handle = add_htn(&mut app, htn);
+    let sup = spawn_supervisor(&mut app, &htn_handle, &TestState::default());
+    app.world_mut().entity_mut(sup).insert(PlanRepair);
+    app.update();
+    replan(&mut app, sup);
+    let plan = |app: &App| {
+        let plan = app.world().get::<Plan>(sup).unwrap();
+        (plan.task_names(), plan.mtr().to_vec())
+    };
+    assert_eq!(plan(&app), (vec!["Flaky".to_string()], vec![0]));
+
+    // repairing skips "Better", its preconditions don't hold, so we fall back to idling
+    complete_first_task(&mut app, sup, false);
+    assert_eq!(plan(&app), (vec!["Idle".to_string()], vec![2]));
+
+    // once "Better" is viable, its plan outranks the repaired one
+    app.world_mut().get_mut::<TestState>(sup).unwrap().tog = true;
+    app.update();
+    assert_eq!(plan(&app), (vec!["Work".to_string()], vec![1]));
+}
+
+#[test]
+fn test_task_timeouts() {
+    #[derive(Resource, Default)]
+    struct TaskEvents(Vec<String>);
+
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    compound_task "Main" {
+        method {
+            subtasks: [Wait]
+        }
+    }
+    primitive_task "Wait" {
+        operator: TestOperator1
+        timeout: 1
+    }
+    "#;
+    let mut app = htn_app::<TestState>();
+    fixed_frame_time(&mut app);
+    app.init_resource::<TaskEvents>();
+    app.add_observer(|t: Trigger<HtnTaskEvent>, mut events: ResMut<TaskEvents>| {
+        let kind = match t.event() {
+            HtnTaskEvent::Executing(_) => "Executing",
+            HtnTaskEvent::Success(_) => "Success",
+            HtnTaskEvent::Failure(_) => "Failure",
+            HtnTaskEvent::TimedOut(_) => "TimedOut",
+        };
+        events
+            .0
+            .push(format!("{kind}({:?})", t.event().task().name));
+    });
+    let htn = parse_htn::<TestState>(src).expect("Failed to parse htn");
+    let Some(Task::Primitive(wait)) = htn.get_task_by_name("Wait") else {
+        panic!("Wait is not a primitive task");
+    };
+    assert_eq!(wait.timeout, Some(1.0));
+    let htn_handle = add_htn(&mut app, htn);
+    let sup = spawn_supervisor(&mut app, &htn_handle, &TestState::default());
+    // the task's own timeout takes precedence over the supervisor's default
+    app.world_mut()
+        .entity_mut(sup)
+        .insert(DefaultTaskTimeout(0.3));
+    app.update();
+    replan(&mut app, sup);
+
+    // the operator never finishes, so once it times out it's despawned and the plan fails
+    for _ in 0..5 {
+        app.update();
+    }
+    assert_eq!(app.world().get::<Plan>(sup).unwrap().id(), 1);
+    for _ in 0..8 {
+        app.update();
+    }
+    assert_eq!(
+        app.world().resource::<TaskEvents>().0,
+        vec![
+            "Executing(\"Wait\")",
+            "TimedOut(\"Wait\")",
+            "Failure(\"Wait\")",
+            "Executing(\"Wait\")",
+        ]
+    );
+    let history = app.world().get::<PlanHistory>(sup).unwrap();
+    assert_eq!(
+        history.last().map(|r| (r.plan_id, r.status)),
+        Some((1, PlanStatus::Failed))
+    );
+    let operators = app
+        .world()
+        .get::<Children>(sup)
+        .unwrap()
+        .iter()
+        .filter(|c| app.world().get::<PlannedTaskId>(**c).is_some())
+        .count();
+    assert_eq!(operators, 1);
+
+    // tasks without a timeout use the supervisor's default
+    let mut app = toggle_app();
+    fixed_frame_time(&mut app);
+    let sup = spawn_toggle_supervisor(&mut app);
+    app.world_mut()
+        .entity_mut(sup)
+        .insert(DefaultTaskTimeout(0.3));
+    app.update();
+    replan(&mut app, sup);
+    for _ in 0..6 {
+        app.update();
+    }
+    assert_eq!(
+        app.world()
+            .get::<PlanHistory>(sup)
+            .unwrap()
+            .last()
+            .map(|r| r.status),
+        Some(PlanStatus::Failed)
+    );
+
+    // timeouts that aren't positive are ignored, rather than failing every task at once
+    let mut app = toggle_app();
+    fixed_frame_time(&mut app);
+    let sup = spawn_toggle_supervisor(&mut app);
+    app.world_mut()
+        .entity_mut(sup)
+        .insert(DefaultTaskTimeout(0.0));
+    app.update();
+    replan(&mut app, sup);
+    for _ in 0..6 {
+        app.update();
+    }
+    assert!(app
+        .world()
+        .get::<PlanHistory>(sup)
+        .unwrap()
+        .last()
+        .is_none());
+    assert_eq!(DefaultTaskTimeout(-1.0).secs(), None);
+    assert_eq!(DefaultTaskTimeout(0.3).secs(), Some(0.3));
+}
+
+#[test]
+fn test_task_event_parent() {
+    #[derive(Resource, Default)]
+    struct Executing(Vec<HtnTaskInfo>);
+
+    let mut app = toggle_app();
+    app.init_resource::<Executing>();
+    app.add_observer(
+        |t: Trigger<HtnTaskEvent>, mut executing: ResMut<Executing>| {
+            if let HtnTaskEvent::Executing(task) = t.event() {
+                executing.0.push(task.clone());
+            }
+        },
+    );
+    let sup = spawn_toggle_supervisor(&mut app);
+    app.update();
+    replan(&mut app, sup);
+    app.update();
+    assert_eq!(
+        app.world().resource::<Executing>().0.first(),
+        Some(&HtnTaskInfo {
+            name: "TurnOn".to_string(),
+            parent: Some("Toggle".to_string()),
+            method_index: Some(0),
+            method_name: None,
+        })
+    );
+}
+
+#[test]
+fn test_task_retries() {
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    compound_task "Main" {
+        method {
+            subtasks: [Flaky, Done]
+        }
+        method {
+            subtasks: [Done]
+        }
+    }
+    primitive_task "Flaky" {
+        operator: TestOperator1
+        preconditions: [tog == false]
+        effects: [tog = true]
+        retries: 2
+        retry_delay: 0.5
+    }
+    primitive_task "Done" {
+        operator: TestOperator1
+    }
+    "#;
+    let mut app = htn_app::<TestState>();
+    fixed_frame_time(&mut app);
+    let htn = parse_htn::<TestState>(src).expect("Failed to parse htn");
+    let Some(Task::Primitive(flaky)) = htn.get_task_by_name("Flaky") else {
+        panic!("Flaky is not a primitive task");
+    };
+    assert_eq!((flaky.retries, flaky.retry_delay), (2, 0.5));
+    let negative = src.replace("retries: 2", "retries: -1");
+    assert!(matches!(
+        parse_htn::<TestState>(&negative),
+        Err(HtnErr::Int { .. })
+    ));
+    let htn_handle = add_htn(&mut app, htn);
+    let sup = spawn_supervisor(&mut app, &htn_handle, &TestState::default());
+    app.update();
+    replan(&mut app, sup);
+
+    let operators = |app: &mut App| {
+        let mut q = app.world_mut().query::<(Entity, &PlannedTaskId)>();
+        q.iter(app.world())
+            .map(|(e, id)| (e, id.name().to_string()))
+            .collect::<Vec<_>>()
+    };
+    fn plan(app: &App, sup: Entity) -> &Plan {
+        app.world().get::<Plan>(sup).unwrap()
+    }
+
+    // failures are retried with a new operator after the delay, without failing the plan
+    for retry in 1..=2 {
+        let failed = operators(&mut app);
+        complete_first_task(&mut app, sup, false);
+        assert_eq!(plan(&app, sup).status(), PlanStatus::Running);
+        assert_eq!(plan(&app, sup).tasks[0].retries, retry);
+        for _ in 0..3 {
+            app.update();
+        }
+        assert_eq!(operators(&mut app), failed);
+        for _ in 0..3 {
+            app.update();
+        }
+        let retried = operators(&mut app);
+        assert_eq!(retried.len(), 1);
+        assert_ne!(retried[0].0, failed[0].0);
+        assert_eq!(retried[0].1, "Flaky");
+    }
+    // once the retries are used up, the plan fails
+    complete_first_task(&mut app, sup, false);
+    assert_eq!(
+        app.world()
+            .get::<PlanHistory>(sup)
+            .unwrap()
+            .last()
+            .map(|r| r.status),
+        Some(PlanStatus::Failed)
+    );
+    assert_eq!(plan(&app, sup).id(), 2);
+    assert_eq!(plan(&app, sup).task_names(), vec!["Flaky", "Done"]);
+
+    // preconditions are checked again before retrying, a failed attempt may have changed things
+    complete_first_task(&mut app, sup, false);
+    app.world_mut()
+        .get_mut::<TestState>(sup)
+        .unwrap()
+        .bypass_change_detection()
+        .tog = true;
+    for _ in 0..6 {
+        app.update();
+    }
+    assert_eq!(plan(&app, sup).task_names(), vec!["Done"]);
+    assert_eq!(
+        app.world()
+            .get::<PlanHistory>(sup)
+            .unwrap()
+            .last()
+            .map(|r| r.status),
+        Some(PlanStatus::Aborted)
+    );
+
+    // a plan replaced while a retry waits for its delay keeps running its own task
+    app.world_mut()
+        .get_mut::<TestState>(sup)
+        .unwrap()
+        .bypass_change_detection()
+        .tog = false;
+    app.world_mut().get_mut::<Plan>(sup).unwrap().abort();
+    replan(&mut app, sup);
+    complete_first_task(&mut app, sup, false);
+    app.world_mut().get_mut::<Plan>(sup).unwrap().abort();
+    replan(&mut app, sup);
+    let replanned = operators(&mut app);
+    assert_eq!(plan(&app, sup).task_names(), vec!["Flaky", "Done"]);
+    assert_eq!(plan(&app, sup).tasks[0].retries, 0);
+    for _ in 0..6 {
+        app.update();
+    }
+    assert_eq!(operators(&mut app), replanned);
+    app.world_mut()
+        .entity_mut(replanned[0].0)
+        .insert(BehaveFinished(true));
+    app.update();
+    assert_eq!(plan(&app, sup).tasks[0].status, TaskStatus::Success);
+    assert_eq!(plan(&app, sup).tasks[1].status, TaskStatus::Running);
+}
+
+#[test]
+fn test_replan_policy() {
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    compound_task "Main" {
+        method {
+            preconditions: [tog == true]
+            subtasks: [Work]
+        }
+        method {
+            subtasks: [Idle]
+        }
+    }
+    primitive_task "Work" {
+        operator: TestOperator1
+        preconditions: [tog == true]
+    }
+    primitive_task "Idle" {
+        operator: TestOperator1
+    }
+    "#;
+    let mut app = htn_app::<TestState>();
+    fixed_frame_time(&mut app);
+    let htn = parse_htn::<TestState>(src).expect("Failed to parse htn");
+    let htn_handle = add_htn(&mut app, htn);
+    let spawn_sup = |app: &mut App, policy: ReplanPolicy| {
+        let state = TestState {
+            tog: true,
+            ..default()
+        };
+        let sup = spawn_supervisor(app, &htn_handle, &state);
+        app.world_mut().entity_mut(sup).insert(policy);
+        app.update();
+        replan(app, sup);
+        sup
+    };
+    fn plan(app: &App, sup: Entity) -> (u32, Vec<String>, PlanStatus) {
+        let plan = app.world().get::<Plan>(sup).unwrap();
+        (plan.id(), plan.task_names(), plan.status())
+    }
+    let work = || vec!["Work".to_string()];
+    let idle = || vec!["Idle".to_string()];
+
+    // changes that leave the plan valid don't replan
+    let sup = spawn_sup(&mut app, ReplanPolicy::on_invalidation());
+    assert_eq!(plan(&app, sup), (1, work(), PlanStatus::Running));
+    app.world_mut().get_mut::<TestState>(sup).unwrap().counter += 1;
+    app.update();
+    assert_eq!(app.world().get::<PlanHistory>(sup).unwrap().last(), None);
+    // but invalidating it does
+    app.world_mut().get_mut::<TestState>(sup).unwrap().tog = false;
+    app.update();
+    assert_eq!(plan(&app, sup), (2, idle(), PlanStatus::Running));
+
+    // finishing the plan doesn't replan, unless the policy says so
+    complete_first_task(&mut app, sup, true);
+    app.update();
+    assert_eq!(plan(&app, sup), (2, idle(), PlanStatus::Succeeded));
+
+    // replanning at an interval
+    app.world_mut()
+        .entity_mut(sup)
+        .insert(ReplanPolicy::every(1.0));
+    for _ in 0..12 {
+        app.update();
+    }
+    assert_eq!(plan(&app, sup), (3, idle(), PlanStatus::Running));
+
+    // policies that don't replan on invalidation don't check the plan when the state changes
+    let sup = spawn_sup(&mut app, ReplanPolicy::on_finish());
+    assert_eq!(plan(&app, sup), (1, work(), PlanStatus::Running));
+    app.world_mut().get_mut::<TestState>(sup).unwrap().tog = false;
+    app.update();
+    assert_eq!(plan(&app, sup), (1, work(), PlanStatus::Running));
+
+    // requests within the minimum interval wait for it to pass
+    let sup = spawn_sup(&mut app, ReplanPolicy::on_change().with_min_interval(1.0));
+    assert_eq!(plan(&app, sup), (1, work(), PlanStatus::Running));
+    app.world_mut()
+        .get_mut::<TestState>(sup)
+        .unwrap()
+        .bypass_change_detection()
+        .tog = false;
+    app.world_mut().trigger_targets(ReplanRequest, sup);
+    replan(&mut app, sup);
+    assert_eq!(plan(&app, sup), (1, work(), PlanStatus::Running));
+    for _ in 0..12 {
+        app.update();
+    }
+    assert_eq!(plan(&app, sup), (2, idle(), PlanStatus::Running));
+}
+
+#[test]
+fn test_relevant_fields() {
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    compound_task "Main" {
+        method {
+            preconditions: [counter >= 5]
+            subtasks: [Work]
+        }
+        method {
+            subtasks: [Idle]
+        }
+    }
+    primitive_task "Work" {
+        operator: TestOperator1
+    }
+    primitive_task "Idle" {
+        operator: TestOperator1
+        preconditions: [e1 == e2]
+    }
+    "#;
+    let mut app = htn_app::<TestState>();
+    count_replans(&mut app);
+    let htn = parse_htn::<TestState>(src).expect("Failed to parse htn");
+    let htn_handle = add_htn(&mut app, htn.clone());
+    let sup = spawn_supervisor(&mut app, &htn_handle, &TestState::default());
+    app.update();
+    replan(&mut app, sup);
+    app.update();
+
+    let plan = app.world().get::<Plan>(sup).unwrap();
+    assert_eq!(plan.task_names(), vec!["Idle".to_string()]);
+    // the preconditions of the higher priority method, and of the remaining task
+    let relevant = plan.relevant_fields(&htn).unwrap();
+    let mut relevant = relevant.into_iter().collect::<Vec<_>>();
+    relevant.sort();
+    assert_eq!(relevant, vec!["counter", "e1", "e2"]);
+    let requests = replan_requests(&app);
+
+    // nothing reads tog, so changing it can't change the plan
+    app.world_mut().get_mut::<TestState>(sup).unwrap().tog = true;
+    app.update();
+    assert_eq!(replan_requests(&app), requests);
+
+    // counter could make the first method applicable
+    app.world_mut().get_mut::<TestState>(sup).unwrap().counter = 1;
+    app.update();
+    assert_eq!(replan_requests(&app), requests + 1);
+
+    // a better method that failed further down still depends on the fields that failed it
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    compound_task "Main" {
+        method {
+            subtasks: [Prepare]
+        }
+        method {
+            subtasks: [Idle]
+        }
+    }
+    compound_task "Prepare" {
+        method {
+            preconditions: [counter >= 0]
+            subtasks: [Equip, Work]
+        }
+    }
+    primitive_task "Equip" {
+        operator: TestOperator1
+        effects: [e1 = e2]
+    }
+    primitive_task "Work" {
+        operator: TestOperator1
+        preconditions: [tog == true]
+    }
+    primitive_task "Idle" {
+        operator: TestOperator1
+    }
+    "#;
+    let htn = parse_htn::<TestState>(src).expect("Failed to parse htn");
+    let plan = HtnPlanner::new(&htn, app.atr()).plan(&TestState::default());
+    assert_eq!(plan.task_names(), vec!["Idle".to_string()]);
+    let mut relevant = plan
+        .relevant_fields(&htn)
+        .unwrap()
+        .into_iter()
+        .collect::<Vec<_>>();
+    relevant.sort();
+    // e2 is copied by an effect, e1 is only written to
+    assert_eq!(relevant, vec!["counter", "e2", "tog"]);
+}
+
+#[test]
+fn test_relevant_nested_fields() {
+    #[derive(Reflect, Default, Clone, Debug)]
+    #[reflect(Default)]
+    struct Shared {
+        coins: i32,
+        gems: i32,
+    }
+
+    #[derive(Reflect, Component, Default, Clone, Debug)]
+    #[reflect(Default, Component)]
+    struct Agent {
+        world: Shared,
+    }
+
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    compound_task "Main" {
+        method {
+            preconditions: [world.coins >= 5]
+            subtasks: [Spend]
+        }
+        method {
+            subtasks: [Idle]
+        }
+    }
+    primitive_task "Spend" {
+        operator: TestOperator1
+    }
+    primitive_task "Idle" {
+        operator: TestOperator1
+    }
+    "#;
+    let mut app = htn_app::<Agent>();
+    count_replans(&mut app);
+    let htn = parse_htn::<Agent>(src).expect("Failed to parse htn");
+    let plan = HtnPlanner::new(&htn, app.atr()).plan(&Agent::default());
+    let relevant = plan.relevant_fields(&htn).unwrap();
+    assert_eq!(
+        relevant.into_iter().collect::<Vec<_>>(),
+        vec!["world.coins"]
+    );
+
+    let htn_handle = add_htn(&mut app, htn);
+    let sup = spawn_supervisor(&mut app, &htn_handle, &Agent::default());
+    app.update();
+    replan(&mut app, sup);
+    app.update();
+    assert_eq!(
+        app.world().get::<Plan>(sup).unwrap().task_names(),
+        vec!["Idle"]
+    );
+    let requests = replan_requests(&app);
+
+    // gems is in the same struct as coins, but nothing reads it
+    app.world_mut().get_mut::<Agent>(sup).unwrap().world.gems = 3;
+    app.update();
+    assert_eq!(replan_requests(&app), requests);
+
+    app.world_mut().get_mut::<Agent>(sup).unwrap().world.coins = 1;
+    app.update();
+    assert_eq!(replan_requests(&app), requests + 1);
+}
+
+/// A second state type, for apps with supervisors of several types.
+#[derive(Reflect, Component, Default, Clone, Debug)]
+#[reflect(Default)]
+struct OtherState {
+    busy: bool,
+}
+
+#[test]
+fn test_multiple_state_types() {
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    compound_task "Main" {
+        method {
+            subtasks: [First, Second]
+        }
+    }
+    primitive_task "First" {
+        operator: TestOperator1
+    }
+    primitive_task "Second" {
+        operator: TestOperator1
+    }
+    "#;
+    let mut app = App::new();
+    // shared setup mustn't depend on resources the app may have added itself
+    app.init_resource::<HtnClaims>();
+    app.add_plugins((
+        MinimalPlugins,
+        AssetPlugin::default(),
+        HtnAssetPlugin::<TestState>::default(),
+        HtnAssetPlugin::<OtherState>::default(),
+        HtnPlugin::<TestState>::default(),
+        HtnPlugin::<OtherState>::default(),
+    ));
+    app.register_type::<TestOperator1>();
+    let test_htn = parse_htn::<TestState>(src).expect("Failed to parse htn");
+    let test_handle = add_htn(&mut app, test_htn);
+    let other_htn = parse_htn::<OtherState>(src).expect("Failed to parse htn");
+    let other_handle = add_htn(&mut app, other_htn);
+    let test_sup = spawn_supervisor(&mut app, &test_handle, &TestState::default());
+    let other_sup = spawn_supervisor(&mut app, &other_handle, &OtherState::default());
+    app.update();
+    replan(&mut app, test_sup);
+    replan(&mut app, other_sup);
+
+    fn statuses(app: &App, sup: Entity) -> (u32, Vec<TaskStatus>) {
+        let plan = app.world().get::<Plan>(sup).unwrap();
+        (
+            plan.id(),
+            plan.tasks.iter().map(|t| t.status.clone()).collect(),
+        )
+    }
+    // each plan started its first task once
+    let started = (1, vec![TaskStatus::Running, TaskStatus::NotStarted]);
+    assert_eq!(statuses(&app, test_sup), started);
+    assert_eq!(statuses(&app, other_sup), started);
+
+    // finishing an operator completes its task once, and only for its own supervisor
+    let operator = app
+        .world()
+        .get::<Children>(other_sup)
+        .unwrap()
+        .iter()
+        .copied()
+        .find(|c| app.world().get::<PlannedTaskId>(*c).is_some())
+        .unwrap();
+    app.world_mut()
+        .entity_mut(operator)
+        .insert(BehaveFinished(true));
+    app.update();
+    assert_eq!(statuses(&app, test_sup), started);
+    assert_eq!(
+        statuses(&app, other_sup),
+        (1, vec![TaskStatus::Success, TaskStatus::Running])
+    ); // and the shared lifecycle reporting ran for both
+    assert!(!app.world().get::<Plan>(test_sup).unwrap().has_transitions());
+    assert!(!app
+        .world()
+        .get::<Plan>(other_sup)
+        .unwrap()
+        .has_transitions());
+}
+
+#[test]
+fn test_plan_validity_from_current_task() {
+    let src = r#"
+    schema {
+        version: 0.1.0
+    }
+    compound_task "Main" {
+        method {
+            subtasks: [TurnOn, Work, Finish]
+        }
+    }
+    primitive_task "TurnOn" {
+        operator: TestOperator1
+        preconditions: [tog == false]
+        effects: [tog = true]
+    }
+    primitive_task "Work" {
+        operator: TestOperator1
+        preconditions: [tog == true]
+        effects: [counter += 1]
+    }
+    primitive_task "Finish" {
+        operator: TestOperator1
+        preconditions: [counter >= 1]
+    }
+    "#;
+    let app = setup_app();
+    let htn = parse_htn::<TestState>(src).expect("Failed to parse htn");
+    let mut state = TestState::default();
+    let mut plan = HtnPlanner::new(&htn, app.atr()).plan(&state);
+    assert_eq!(plan.task_names(), vec!["TurnOn", "Work", "Finish"]);
+    assert!(plan.check_validity(&htn, state.clone(), app.atr()));
+
+    // TurnOn's precondition no longer holds once it has started turning on
+    let turn_on = plan.next_task_to_execute().unwrap();
+    state.tog = true;
+    assert!(plan.check_validity(&htn, state.clone(), app.atr()));
+    // or once it has completed
+    plan.report_task_completion(&turn_on, true);
+    assert!(plan.check_validity(&htn, state.clone(), app.atr()));
+
+    // Work's effects count for Finish while it's running
+    let work = plan.next_task_to_execute().unwrap();
+    assert!(plan.check_validity(&htn, state.clone(), app.atr()));
+    // Work doesn't turn tog on itself, so it's no longer valid if tog goes off
+    state.tog = false;
+    assert!(!plan.check_validity(&htn, state.clone(), app.atr()));
+    state.tog = true;
+    plan.report_task_completion(&work, true);
+    state.counter = 1;
+    assert!(plan.check_validity(&htn, state.clone(), app.atr()));
+
+    // but tasks that haven't started are still checked
+    state.counter = 0;
+    assert!(!plan.check_validity(&htn, state.clone(), app.atr()));
+}
@@ -0,0 +1,141 @@
+use crate::HtnStateTrait;
+use bevy::prelude::*;
+
+/// A record of the decisions the planner made while decomposing the root task.
+///
+/// Enable with [`HtnPlanner::with_trace`](crate::prelude::HtnPlanner::with_trace), and it will be
+/// returned on the [`Plan`](crate::prelude::Plan).
+///
+/// Insert a `PlanTrace` component on an `HtnSupervisor` entity to have the executor record a trace
+/// every time it replans, replacing the component with the latest trace.
+#[derive(Reflect, Debug, Clone, Default, Component)]
+#[reflect(Default, Component)]
+pub struct PlanTrace {
+    pub steps: Vec<TraceStep>,
+}
+
+impl PlanTrace {
+    pub fn push(&mut self, step: TraceStep) {
+        self.steps.push(step);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+impl std::fmt::Display for PlanTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for step in self.steps.iter() {
+            writeln!(f, "{step}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Reflect, Debug, Clone, PartialEq)]
+pub enum TraceStep {
+    /// A task was popped off the task stack for evaluation.
+    VisitTask { task: String },
+    /// A method of a compound task was evaluated. `failed_condition` is the syntax of the first
+    /// precondition that didn't hold, or None if the method was chosen.
+    TryMethod {
+        task: String,
+        method_index: usize,
+        method_name: Option<String>,
+        failed_condition: Option<String>,
+    },
+    /// A primitive task's preconditions held, it was added to the plan and its effects applied to
+    /// the working state.
+    AddPrimitive {
+        task: String,
+        state_diff: Vec<FieldChange>,
+    },
+    /// A primitive task's preconditions did not hold.
+    RejectPrimitive {
+        task: String,
+        failed_condition: Option<String>,
+    },
+    /// The planner restored a previous decomposition, and will resume evaluating `task` skipping
+    /// methods before `skip_methods`.
+    Backtrack { task: String, skip_methods: usize },
+}
+
+impl std::fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceStep::VisitTask { task } => write!(f, "visit {task}"),
+            TraceStep::TryMethod {
+                task,
+                method_index,
+                method_name,
+                failed_condition,
+            } => {
+                let name = method_name.as_deref().unwrap_or("");
+                match failed_condition {
+                    Some(cond) => {
+                        write!(f, "  {task} method #{method_index} {name} failed: `{cond}`")
+                    }
+                    None => write!(f, "  {task} method #{method_index} {name} chosen"),
+                }
+            }
+            TraceStep::AddPrimitive { task, state_diff } => {
+                write!(f, "  add {task}")?;
+                for change in state_diff.iter() {
+                    write!(f, "\n    {change}")?;
+                }
+                Ok(())
+            }
+            TraceStep::RejectPrimitive {
+                task,
+                failed_condition,
+            } => write!(
+                f,
+                "  reject {task}: `{}`",
+                failed_condition.as_deref().unwrap_or("???")
+            ),
+            TraceStep::Backtrack { task, skip_methods } => {
+                write!(f, "backtrack to {task}, skipping {skip_methods} methods")
+            }
+        }
+    }
+}
+
+/// A change to a single state field, with values formatted using their Debug representation.
+#[derive(Reflect, Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl std::fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} -> {}", self.field, self.old, self.new)
+    }
+}
+
+/// Compares every field of two states via reflection, returning the ones that differ.
+pub fn state_diff<T: HtnStateTrait>(before: &T, after: &T) -> Vec<FieldChange> {
+    let (Ok(before), Ok(after)) = (
+        before.reflect_ref().as_struct(),
+        after.reflect_ref().as_struct(),
+    ) else {
+        return Vec::new();
+    };
+    let mut changes = Vec::new();
+    for (idx, old) in before.iter_fields().enumerate() {
+        let Some(new) = after.field_at(idx) else {
+            continue;
+        };
+        if old.reflect_partial_eq(new).unwrap_or(false) {
+            continue;
+        }
+        changes.push(FieldChange {
+            field: before.name_at(idx).unwrap_or("???").to_string(),
+            old: format!("{old:?}"),
+            new: format!("{new:?}"),
+        });
+    }
+    changes
+}
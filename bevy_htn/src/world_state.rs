@@ -0,0 +1,134 @@
+use crate::{htn::state_field, prelude::*, HtnStateTrait};
+use bevy::{
+    prelude::*,
+    reflect::{GetTypeRegistration, Typed},
+};
+use std::marker::PhantomData;
+
+/// The name of the state field holding each agent's copy of the shared world state.
+/// Conditions and effects refer to world state fields as `world.some_field`.
+pub const WORLD_FIELD: &str = "world";
+
+/// Facts about the world shared by every supervisor, eg. which coins exist, or which bridges have
+/// been checked.
+///
+/// Add a `world: W` field to your state type, and an [`HtnWorldStatePlugin`], and each
+/// supervisor's copy of the world state is kept in sync with this resource. Changing the resource
+/// causes the supervisors to replan. When a task whose effects write to `world.` fields
+/// completes, the effects are applied to this resource, so other agents see the changes.
+#[derive(Resource, Reflect, Debug, Default, Clone, Deref, DerefMut)]
+#[reflect(Resource)]
+pub struct HtnWorldState<W: Reflect + TypePath>(pub W);
+
+/// Triggered on a supervisor entity when a completed task had effects on the world state.
+#[derive(Event, Debug, Clone)]
+pub struct HtnWorldEffects {
+    pub task: TaskId,
+}
+
+/// Shares an [`HtnWorldState<W>`] between supervisors with state `T`, which must have a
+/// `world: W` field.
+pub struct HtnWorldStatePlugin<T: HtnStateTrait, W> {
+    phantom: PhantomData<(T, W)>,
+}
+
+impl<T: HtnStateTrait, W> Default for HtnWorldStatePlugin<T, W> {
+    fn default() -> Self {
+        Self {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T, W> Plugin for HtnWorldStatePlugin<T, W>
+where
+    T: HtnStateTrait,
+    W: Reflect + FromReflect + Typed + GetTypeRegistration + TypePath + Default + Clone,
+{
+    fn build(&self, app: &mut App) {
+        app.register_type::<W>();
+        app.register_type::<HtnWorldState<W>>();
+        app.init_resource::<HtnWorldState<W>>();
+        app.add_systems(PreUpdate, sync_world_state::<T, W>);
+        app.add_observer(on_world_effects::<T, W>);
+    }
+}
+
+/// Copies the world state into each supervisor's state, when it changes or a supervisor is added.
+fn sync_world_state<T: HtnStateTrait, W: Reflect + TypePath>(
+    world: Res<HtnWorldState<W>>,
+    mut q: Query<&mut T, With<HtnSupervisor<T>>>,
+) {
+    for mut state in q.iter_mut() {
+        if !world.is_changed() && !state.is_added() {
+            continue;
+        }
+        let Some(agent_world) = world_field(state.as_ref()) else {
+            warn!("State has no `{WORLD_FIELD}` field to share world state with");
+            continue;
+        };
+        if agent_world
+            .reflect_partial_eq(world.0.as_partial_reflect())
+            .unwrap_or(false)
+        {
+            continue;
+        }
+        // marks the state as changed, so the supervisor replans
+        if let Some(agent_world) = state
+            .reflect_mut()
+            .as_struct()
+            .ok()
+            .and_then(|s| s.field_mut(WORLD_FIELD))
+        {
+            agent_world.apply(world.0.as_partial_reflect());
+        }
+    }
+}
+
+/// Applies the effects of a completed task to the shared world state.
+fn on_world_effects<T: HtnStateTrait, W: Reflect + TypePath>(
+    t: Trigger<HtnWorldEffects>,
+    q: Query<(&HtnSupervisor<T>, &T)>,
+    assets: Res<Assets<HtnAsset<T>>>,
+    atr: Res<AppTypeRegistry>,
+    mut world: ResMut<HtnWorldState<W>>,
+) {
+    let Ok((htn_supervisor, state)) = q.get(t.entity()) else {
+        return;
+    };
+    let Some(Task::Primitive(primitive)) = assets
+        .get(&htn_supervisor.htn_handle)
+        .and_then(|h| h.htn.get_task(t.event().task))
+    else {
+        warn!("Primitive task {:?} not found", t.event().task);
+        return;
+    };
+    // other agents may have changed the world since our copy was synced, so apply the effects
+    // to the latest world state rather than copying ours.
+    let mut state = state.clone();
+    let Some(agent_world) = state
+        .reflect_mut()
+        .as_struct()
+        .ok()
+        .and_then(|s| s.field_mut(WORLD_FIELD))
+    else {
+        return;
+    };
+    agent_world.apply(world.0.as_partial_reflect());
+    primitive.apply_effects(&mut state, atr.as_ref());
+    if let Some(new_world) = world_field(&state) {
+        world.0.apply(new_world);
+    }
+}
+
+fn world_field<T: HtnStateTrait>(state: &T) -> Option<&dyn PartialReflect> {
+    state_field(state.reflect_ref().as_struct().ok()?, WORLD_FIELD)
+}
+
+/// True if the effect writes to the shared world state.
+pub(crate) fn is_world_effect(effect: &Effect) -> bool {
+    effect
+        .field()
+        .split_once('.')
+        .is_some_and(|(head, _)| head == WORLD_FIELD)
+}